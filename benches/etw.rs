@@ -11,7 +11,7 @@ use tracing_subscriber::{self, prelude::*};
 pub fn etw_benchmark(c: &mut Criterion) {
     let builder = LayerBuilder::new("etw_bench");
     //let provider_id = builder.get_provider_id();
-    let _subscriber = tracing_subscriber::registry()
+    tracing_subscriber::registry()
         .with(builder.__build_for_test().unwrap())
         .init();
 
@@ -8,7 +8,7 @@ use tracing_subscriber::{self, prelude::*};
 #[cfg(target_os = "linux")]
 pub fn user_events_benchmark(c: &mut Criterion) {
     let builder = LayerBuilder::new("user_events_bench");
-    let _subscriber = tracing_subscriber::registry()
+    tracing_subscriber::registry()
         .with(builder.__build_for_test().unwrap())
         .init();
 
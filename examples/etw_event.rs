@@ -1,8 +1,21 @@
 use tracing::Level;
-use tracing_etw::{etw_event, LayerBuilder};
+use tracing_etw::{etw_event, event_catalog, LayerBuilder};
 use tracing_subscriber::{self, fmt::format::FmtSpan, prelude::*};
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--list-events") {
+        for event in event_catalog() {
+            println!(
+                "{}: keyword {:#x}, level {}, fields {:?}",
+                event.name(),
+                event.keyword(),
+                event.level(),
+                event.fields().collect::<Vec<_>>()
+            );
+        }
+        return;
+    }
+
     tracing_subscriber::registry()
         .with(LayerBuilder::new("ExampleProvEtwEvent").build().unwrap())
         .with(tracing_subscriber::fmt::layer().with_span_events(FmtSpan::ACTIVE))
@@ -7,14 +7,157 @@
 #[doc(hidden)]
 #[repr(C)]
 pub struct EventMetadata {
+    pub header: RecordHeader,
     pub kw: u64,
     pub identity: tracing::callsite::Identifier,
     pub event_tag: u32,
+    pub options: EventOptions,
+    pub description: EventDescription,
 }
 
-// An EventMetadata with a hash, because Identity doesn't implement comparisons but we need a stable ordering.
-#[derive(Clone)]
-pub(crate) struct ParsedEventMetadata {
-    pub(crate) identity_hash: u64,
-    pub(crate) meta: &'static EventMetadata,
+// The first field of every record in the linker section. The magic identifies the kind of record.
+// Other versions of this crate in the same binary write their records to the same linker section,
+// so a record is only read if its header matches the layout this version was built with.
+// Versions from before the header was added start their records with the 8 byte keyword,
+// so the header can always be read safely.
+#[doc(hidden)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordHeader {
+    pub magic: u32,
+    pub version: u16,
+    pub size: u16,
+}
+
+impl RecordHeader {
+    pub const MAGIC: u32 = u32::from_le_bytes(*b"TEtw");
+    pub const KEYWORD_MAGIC: u32 = u32::from_le_bytes(*b"TEkw");
+    // Must be incremented whenever the layout of EventMetadata or KeywordDefinition, or of anything
+    // they contain, changes.
+    pub const VERSION: u16 = 2;
+
+    pub const CURRENT: RecordHeader = RecordHeader {
+        magic: Self::MAGIC,
+        version: Self::VERSION,
+        size: core::mem::size_of::<EventMetadata>() as u16,
+    };
+
+    pub const KEYWORD: RecordHeader = RecordHeader {
+        magic: Self::KEYWORD_MAGIC,
+        version: Self::VERSION,
+        size: core::mem::size_of::<KeywordDefinition>() as u16,
+    };
+}
+
+// A keyword defined with etw_keywords!. These are written to the same linker section as the
+// EventMetadata records, so that the names of keywords can be found at runtime and by tools.
+#[doc(hidden)]
+#[repr(C)]
+pub struct KeywordDefinition {
+    pub header: RecordHeader,
+    pub value: u64,
+    pub name: StaticStr,
+}
+
+// Called by etw_event! and etw_span! the first time they run, when the runtime_metadata feature is enabled.
+#[cfg(feature = "runtime_metadata")]
+pub fn register_event_metadata(meta: &'static EventMetadata) {
+    crate::statics::register_event_metadata(meta)
+}
+
+// Optional event header values that can be set with the `etw_event!` macro.
+#[doc(hidden)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventOptions {
+    pub opcode: u8,
+    pub version: u8,
+    pub channel: u8,
+    // 0 if the event was not given an ID
+    pub id: u16,
+}
+
+impl EventOptions {
+    // Channel 11 is the channel that ETW assigns to TraceLogging events.
+    pub const DEFAULT: EventOptions = EventOptions {
+        opcode: 0,
+        version: 0,
+        channel: 11,
+        id: 0,
+    };
+}
+
+// A description of an etw_event! or etw_span! callsite that tools can read from a compiled binary
+// without running it (see the tracing-etw-catalog crate). The same information is available at
+// runtime from the tracing Metadata of the callsite, but that type does not have a stable layout.
+#[doc(hidden)]
+#[repr(C)]
+pub struct EventDescription {
+    pub name: StaticStr,
+    pub target: StaticStr,
+    pub file: StaticStr,
+    pub fields: *const StaticStr,
+    pub field_count: usize,
+    pub line: u32,
+    // 1 (error) through 5 (trace)
+    pub level: u8,
+    pub is_span: u8,
+}
+
+// SAFETY The pointers only ever point to static, immutable data
+unsafe impl Sync for EventDescription {}
+
+#[doc(hidden)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct StaticStr {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+// SAFETY The pointer only ever points to static, immutable data
+unsafe impl Sync for StaticStr {}
+
+impl StaticStr {
+    pub const fn new(s: &'static str) -> Self {
+        StaticStr {
+            ptr: s.as_ptr(),
+            len: s.len(),
+        }
+    }
+
+    // SAFETY The StaticStr must have been created with StaticStr::new
+    pub unsafe fn as_str(&self) -> &'static str {
+        unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(self.ptr, self.len)) }
+    }
+
+    pub const fn array<const N: usize>(strs: &[&'static str]) -> [StaticStr; N] {
+        let mut result = [StaticStr::new(""); N];
+        let mut i = 0;
+        while i < N {
+            result[i] = StaticStr::new(strs[i]);
+            i += 1;
+        }
+        result
+    }
+}
+
+// True if this build registers metadata at runtime, so nothing is written to the linker section.
+// Tools that read the section from a binary use this to know that it will be empty.
+pub const RUNTIME_METADATA: bool = cfg!(feature = "runtime_metadata");
+
+// The ID that LayerBuilder::with_hashed_event_ids gives an event without an id: argument.
+// Tools use this to write the same IDs that the layer writes.
+pub fn hashed_event_id(provider_name: &str, event_name: &str) -> u16 {
+    crate::layer::HashedEventIds::new(provider_name).id(event_name)
+}
+
+pub const fn level_number(level: tracing::Level) -> u8 {
+    match level {
+        tracing::Level::ERROR => 1,
+        tracing::Level::WARN => 2,
+        tracing::Level::INFO => 3,
+        tracing::Level::DEBUG => 4,
+        tracing::Level::TRACE => 5,
+    }
 }
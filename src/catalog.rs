@@ -0,0 +1,217 @@
+use std::iter::FusedIterator;
+
+use tracing::Level;
+
+use crate::{_details::EventMetadata, statics::EventMetadataEnumerator, Opcode};
+
+/// An `etw_event!` or `etw_span!` callsite that is compiled into the binary.
+///
+/// Returned by [event_catalog].
+#[derive(Clone, Copy)]
+pub struct CatalogEntry {
+    meta: &'static EventMetadata,
+}
+
+impl CatalogEntry {
+    fn tracing_metadata(&self) -> &'static tracing::Metadata<'static> {
+        self.meta.identity.0.metadata()
+    }
+
+    /// The name of the event or span.
+    pub fn name(&self) -> &'static str {
+        self.tracing_metadata().name()
+    }
+
+    /// The target of the event or span.
+    pub fn target(&self) -> &'static str {
+        self.tracing_metadata().target()
+    }
+
+    /// The level of the event or span.
+    pub fn level(&self) -> Level {
+        *self.tracing_metadata().level()
+    }
+
+    /// The keyword of the event or span.
+    pub fn keyword(&self) -> u64 {
+        self.meta.kw
+    }
+
+    /// The names of the keywords defined with [crate::etw_keywords!] whose bits are all set in
+    /// the keyword of the event or span, in order of value.
+    pub fn keyword_names(&self) -> impl Iterator<Item = &'static str> {
+        keyword_names(self.meta.kw).into_iter()
+    }
+
+    /// The event tag of the event or span.
+    pub fn tag(&self) -> u32 {
+        self.meta.event_tag
+    }
+
+    /// The opcode set with the `opcode:` argument of `etw_event!`.
+    pub fn opcode(&self) -> Opcode {
+        Opcode::from_int(self.meta.options.opcode)
+    }
+
+    /// The version set with the `version:` argument of `etw_event!`.
+    pub fn version(&self) -> u8 {
+        self.meta.options.version
+    }
+
+    /// The ID set with the `id:` argument of `etw_event!`, or 0 if it was not set.
+    ///
+    /// Events without an ID are given one when logged by a layer built with
+    /// [crate::LayerBuilder::with_hashed_event_ids].
+    pub fn id(&self) -> u16 {
+        self.meta.options.id
+    }
+
+    /// True if the callsite is an `etw_span!`, rather than an `etw_event!`.
+    pub fn is_span(&self) -> bool {
+        self.tracing_metadata().is_span()
+    }
+
+    /// The names of the fields of the event or span, including `message` if it has one.
+    pub fn fields(&self) -> impl Iterator<Item = &'static str> {
+        self.tracing_metadata()
+            .fields()
+            .iter()
+            .map(|field| field.name())
+    }
+
+    /// The source file of the callsite, if it is known.
+    pub fn file(&self) -> Option<&'static str> {
+        self.tracing_metadata().file()
+    }
+
+    /// The line of the callsite in its source file, if it is known.
+    pub fn line(&self) -> Option<u32> {
+        self.tracing_metadata().line()
+    }
+}
+
+impl std::fmt::Debug for CatalogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CatalogEntry")
+            .field("name", &self.name())
+            .field("target", &self.target())
+            .field("level", &self.level())
+            .field("keyword", &self.keyword())
+            .field("keyword_names", &self.keyword_names().collect::<Vec<_>>())
+            .field("tag", &self.tag())
+            .field("fields", &self.fields().collect::<Vec<_>>())
+            .field("file", &self.file())
+            .field("line", &self.line())
+            .finish()
+    }
+}
+
+// The names of the keywords defined with etw_keywords! that are set in the given keyword
+pub(crate) fn keyword_names(keyword: u64) -> Vec<&'static str> {
+    let mut definitions: Vec<(u64, &'static str)> = crate::statics::keyword_definitions()
+        .into_iter()
+        .filter(|def| def.value != 0 && keyword & def.value == def.value)
+        // SAFETY The name was created from a &'static str by etw_keywords!
+        .map(|def| (def.value, unsafe { def.name.as_str() }))
+        .collect();
+    // The same keyword may be defined by more than one copy of the crate that defines it
+    definitions.sort_unstable();
+    definitions.dedup();
+    definitions.into_iter().map(|(_, name)| name).collect()
+}
+
+/// An iterator over every `etw_event!` and `etw_span!` callsite compiled into the binary.
+///
+/// Returned by [event_catalog].
+pub struct EventCatalog {
+    inner: EventMetadataEnumerator,
+}
+
+impl Iterator for EventCatalog {
+    type Item = CatalogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|meta| CatalogEntry { meta })
+    }
+}
+
+impl FusedIterator for EventCatalog {}
+
+/// Iterate over every `etw_event!` and `etw_span!` callsite compiled into the binary,
+/// whether or not it has been called yet.
+///
+/// This can be used to list the events a program may log, or to generate collection
+/// profiles from their keywords and levels. Events logged with the `tracing` macros
+/// (e.g. `event!`) are not included.
+///
+/// The catalog is empty on platforms other than Windows and Linux.
+///
+/// ```
+/// use tracing_etw::event_catalog;
+///
+/// for event in event_catalog() {
+///     println!("{} ({}): keyword {:#x}, level {}", event.name(), event.target(), event.keyword(), event.level());
+/// }
+/// ```
+pub fn event_catalog() -> EventCatalog {
+    EventCatalog {
+        inner: crate::statics::event_metadata(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tracing::Level;
+
+    use super::{event_catalog, keyword_names};
+    use crate::{etw_event, etw_keywords};
+
+    etw_keywords! {
+        CatalogNetwork = 0x1000_0000,
+        CatalogStorage = 0x2000_0000,
+        CatalogBoth = 0x3000_0000,
+    }
+
+    #[test]
+    fn catalog_entry() {
+        etw_event!(target: "catalog_test", name: "Catalog.Entry", Level::WARN, 0, 200, { id = 1, message = "Entry" });
+
+        let entry = event_catalog().find(|event| event.tag() == 200);
+
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        {
+            let entry = entry.unwrap();
+            assert_eq!(entry.name(), "Catalog.Entry");
+            assert_eq!(entry.target(), "catalog_test");
+            assert_eq!(entry.level(), Level::WARN);
+            assert_eq!(entry.keyword(), 0);
+            assert!(!entry.is_span());
+            assert_eq!(entry.fields().collect::<Vec<_>>(), ["id", "message"]);
+            assert_eq!(entry.file(), Some(file!()));
+            assert_eq!(entry.keyword_names().count(), 0);
+        }
+        #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+        assert!(entry.is_none());
+    }
+
+    // The keywords are not used by any events, so the sum in statics::test::test_ten is unaffected
+    #[test]
+    fn named_keywords() {
+        let names = keyword_names(CatalogNetwork | CatalogBoth);
+
+        #[cfg(all(
+            any(target_os = "windows", target_os = "linux"),
+            not(feature = "runtime_metadata")
+        ))]
+        {
+            assert_eq!(names, ["CatalogNetwork", "CatalogStorage", "CatalogBoth"]);
+            assert_eq!(keyword_names(CatalogStorage), ["CatalogStorage"]);
+            assert!(keyword_names(0x4000_0000).is_empty());
+        }
+        #[cfg(not(all(
+            any(target_os = "windows", target_os = "linux"),
+            not(feature = "runtime_metadata")
+        )))]
+        assert!(names.is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{
+    directives::{self, Directives},
+    native::{CommonSchemaOutput, OutputMode},
+    FieldMapping, LayerBuilder, Redaction,
+};
+
+/// The configuration of a layer, which can be deserialized with serde (e.g. from a TOML file)
+/// and converted into a builder with [LayerBuilder::from_config].
+///
+/// Only `provider_name` is required. Settings that are not given keep the defaults of
+/// [LayerBuilder].
+///
+/// ```toml
+/// provider_name = "MyProvider"
+/// provider_group = "mygroup"
+/// default_keyword = 0x10
+/// output = "common_schema"
+///
+/// [targets]
+/// myapp = "debug"
+/// hyper = "warn"
+///
+/// [target_keywords]
+/// "myapp::db" = 0x20
+///
+/// [field_redactions]
+/// password = "drop"
+/// email = { mask = "***" }
+/// user_id = "hash"
+///
+/// [field_mappings]
+/// request_id = { part_b = "requestId" }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct LayerConfig {
+    /// The provider name, as given to [LayerBuilder::new].
+    pub provider_name: String,
+    /// The provider ID, as given to [LayerBuilder::with_provider_id]. Defaults to a hash of
+    /// the provider name.
+    #[serde(default)]
+    pub provider_id: Option<String>,
+    /// The provider group, as given to [LayerBuilder::with_provider_group].
+    /// For ETW, this is a GUID.
+    #[serde(default)]
+    pub provider_group: Option<String>,
+    /// The keyword of events without one, as given to [LayerBuilder::with_default_keyword].
+    #[serde(default)]
+    pub default_keyword: Option<u64>,
+    /// How events are written.
+    #[serde(default)]
+    pub output: LayerOutput,
+    /// The level of each target to build the layer with (e.g. `"debug"`, or `"off"`).
+    /// See [LayerBuilder::build_with_targets].
+    #[serde(default)]
+    pub targets: Option<BTreeMap<String, String>>,
+    /// The keyword of each target, as given to [LayerBuilder::with_target_keywords].
+    #[serde(default)]
+    pub target_keywords: BTreeMap<String, u64>,
+    /// The redaction of each field name, as given to [LayerBuilder::with_field_redaction].
+    #[serde(default)]
+    pub field_redactions: BTreeMap<String, Redaction>,
+    /// The redaction of each target, as given to [LayerBuilder::with_target_redaction].
+    #[serde(default)]
+    pub target_redactions: BTreeMap<String, Redaction>,
+    /// The mapping of each field name, as given to [LayerBuilder::with_field_mapping].
+    /// Only used with [LayerOutput::CommonSchema].
+    #[serde(default)]
+    pub field_mappings: BTreeMap<String, FieldMapping>,
+}
+
+/// How the events of a layer configured with a [LayerConfig] are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerOutput {
+    /// Events are written as with [LayerBuilder::new].
+    #[default]
+    Normal,
+    /// Events are written in the Common Schema 4.0 mapping, as with
+    /// [LayerBuilder::new_common_schema_events]. Can also be given as `"cs"`.
+    #[serde(alias = "cs")]
+    CommonSchema,
+}
+
+impl LayerConfig {
+    /// A configuration for the given provider name, with every other setting left at
+    /// its default.
+    pub fn new(provider_name: &str) -> Self {
+        LayerConfig {
+            provider_name: provider_name.to_string(),
+            provider_id: None,
+            provider_group: None,
+            default_keyword: None,
+            output: LayerOutput::Normal,
+            targets: None,
+            target_keywords: BTreeMap::new(),
+            field_redactions: BTreeMap::new(),
+            target_redactions: BTreeMap::new(),
+            field_mappings: BTreeMap::new(),
+        }
+    }
+
+    // The settings that can also be given as directives. Errors are returned as a description
+    // of the setting that is invalid.
+    pub(crate) fn to_directives(&self) -> Result<Directives, String> {
+        if self.provider_name.is_empty() {
+            return Err("the provider name is empty".to_string());
+        }
+        if self.output == LayerOutput::Normal && !self.field_mappings.is_empty() {
+            return Err("field mappings are only used by Common Schema output".to_string());
+        }
+
+        let provider_id = self
+            .provider_id
+            .as_deref()
+            .map(|id| {
+                directives::parse_guid(id).ok_or_else(|| format!("{id:?} is not a valid GUID"))
+            })
+            .transpose()?;
+        let provider_group = self
+            .provider_group
+            .as_deref()
+            .map(|group| {
+                directives::parse_group(group)
+                    .ok_or_else(|| format!("{group:?} is not a valid provider group"))
+            })
+            .transpose()?;
+        let targets = self
+            .targets
+            .as_ref()
+            .map(|targets| {
+                targets
+                    .iter()
+                    .map(|(target, level)| directives::parse_level(target, level))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+
+        Ok(Directives {
+            provider_name: self.provider_name.clone(),
+            provider_id,
+            provider_group,
+            default_keyword: self.default_keyword,
+            targets,
+            common_schema: self.output == LayerOutput::CommonSchema,
+        })
+    }
+
+    // Applies the settings that cannot be given as directives and are used by every output mode
+    pub(crate) fn apply_rules<OutMode: OutputMode + 'static>(
+        &self,
+        mut builder: LayerBuilder<OutMode>,
+    ) -> LayerBuilder<OutMode> {
+        builder = builder.with_target_keywords(
+            self.target_keywords
+                .iter()
+                .map(|(target, keyword)| (target, *keyword)),
+        );
+        for (field_name, redaction) in &self.field_redactions {
+            builder = builder.with_field_redaction(field_name, redaction.clone());
+        }
+        for (target, redaction) in &self.target_redactions {
+            builder = builder.with_target_redaction(target, redaction.clone());
+        }
+        builder
+    }
+
+    pub(crate) fn apply_field_mappings(
+        &self,
+        mut builder: LayerBuilder<CommonSchemaOutput>,
+    ) -> LayerBuilder<CommonSchemaOutput> {
+        for (field_name, mapping) in &self.field_mappings {
+            builder = builder.with_field_mapping(field_name, mapping.clone());
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tracing::metadata::LevelFilter;
+
+    use super::*;
+
+    #[test]
+    fn deserialize_config() {
+        let config: LayerConfig = serde_json::from_str(
+            r#"{
+                "provider_name": "MyProvider",
+                "default_keyword": 16,
+                "output": "cs",
+                "targets": { "myapp": "debug", "hyper": "warn" },
+                "field_redactions": { "password": "drop", "email": { "mask": "***" } },
+                "field_mappings": { "request_id": { "part_b": "requestId" } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.output, LayerOutput::CommonSchema);
+        assert_eq!(config.field_redactions["password"], Redaction::Drop);
+        assert_eq!(
+            config.field_redactions["email"],
+            Redaction::Mask("***".to_string())
+        );
+        assert_eq!(
+            config.field_mappings["request_id"],
+            FieldMapping::PartB("requestId".to_string())
+        );
+
+        let directives = config.to_directives().unwrap();
+        assert_eq!(directives.provider_name, "MyProvider");
+        assert_eq!(directives.default_keyword, Some(0x10));
+        assert!(directives.common_schema);
+        assert_eq!(
+            directives.targets,
+            Some(vec![
+                ("hyper".to_string(), LevelFilter::WARN),
+                ("myapp".to_string(), LevelFilter::DEBUG),
+            ])
+        );
+
+        assert!(serde_json::from_str::<LayerConfig>(r#"{ "default_keyword": 16 }"#).is_err());
+        assert!(serde_json::from_str::<LayerConfig>(
+            r#"{ "provider_name": "MyProvider", "colour": "blue" }"#
+        )
+        .is_err());
+
+        let mut config = LayerConfig::new("MyProvider");
+        config.targets = Some(BTreeMap::from([("myapp".to_string(), "loud".to_string())]));
+        assert!(config.to_directives().is_err());
+
+        let mut config = LayerConfig::new("MyProvider");
+        config
+            .field_mappings
+            .insert("request_id".to_string(), FieldMapping::Drop);
+        assert!(config.to_directives().is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use std::str::FromStr;
+
+use tracing::metadata::LevelFilter;
+
+use crate::native::{GuidWrapper, ProviderGroupType};
+
+// The settings parsed from a directive string such as
+// `provider=MyProv;group=mygroup;keyword=0x10;targets=myapp=debug,hyper=warn;format=cs`.
+// Settings that are not given keep the defaults of LayerBuilder.
+pub(crate) struct Directives {
+    pub(crate) provider_name: String,
+    pub(crate) provider_id: Option<GuidWrapper>,
+    pub(crate) provider_group: Option<ProviderGroupType>,
+    pub(crate) default_keyword: Option<u64>,
+    pub(crate) targets: Option<Vec<(String, LevelFilter)>>,
+    pub(crate) common_schema: bool,
+}
+
+// Directives are separated by `;`, and each is a `key=value` pair. If a key is repeated, the
+// last value is used. Errors are returned as a description of the directive that is invalid.
+pub(crate) fn parse(directives: &str) -> Result<Directives, String> {
+    let mut provider_name = None;
+    let mut provider_id = None;
+    let mut provider_group = None;
+    let mut default_keyword = None;
+    let mut targets = None;
+    let mut common_schema = false;
+
+    for directive in directives.split(';').map(str::trim) {
+        if directive.is_empty() {
+            continue;
+        }
+        let Some((key, value)) = directive.split_once('=') else {
+            return Err(format!("{directive:?} is not a key=value pair"));
+        };
+        let value = value.trim();
+
+        match key.trim() {
+            "provider" if value.is_empty() => return Err("the provider name is empty".to_string()),
+            "provider" => provider_name = Some(value.to_string()),
+            "guid" => {
+                provider_id = Some(
+                    parse_guid(value).ok_or_else(|| format!("{value:?} is not a valid GUID"))?,
+                )
+            }
+            "group" => {
+                provider_group = Some(
+                    parse_group(value)
+                        .ok_or_else(|| format!("{value:?} is not a valid provider group"))?,
+                )
+            }
+            "keyword" => {
+                default_keyword = Some(
+                    parse_keyword(value)
+                        .ok_or_else(|| format!("{value:?} is not a valid keyword"))?,
+                )
+            }
+            "targets" => targets = Some(parse_targets(value)?),
+            "format" => {
+                common_schema = match value {
+                    "normal" => false,
+                    "cs" | "common_schema" => true,
+                    _ => return Err(format!("{value:?} is not an output format")),
+                }
+            }
+            _ => return Err(format!("{directive:?} is not a known directive")),
+        }
+    }
+
+    Ok(Directives {
+        provider_name: provider_name.ok_or("a provider directive is required")?,
+        provider_id,
+        provider_group,
+        default_keyword,
+        targets,
+        common_schema,
+    })
+}
+
+pub(crate) fn parse_guid(value: &str) -> Option<GuidWrapper> {
+    let value = value.trim_start_matches('{').trim_end_matches('}');
+    crate::native::native_guid::try_parse(value).map(|guid| GuidWrapper::from(&guid))
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn parse_group(value: &str) -> Option<ProviderGroupType> {
+    parse_guid(value).map(Into::into)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn parse_group(value: &str) -> Option<ProviderGroupType> {
+    Some(value.to_string().into())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+pub(crate) fn parse_group(_value: &str) -> Option<ProviderGroupType> {
+    Some(Default::default())
+}
+
+fn parse_keyword(value: &str) -> Option<u64> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// A comma separated list of targets, each optionally followed by `=level`.
+// Targets without a level are enabled at every level.
+fn parse_targets(value: &str) -> Result<Vec<(String, LevelFilter)>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+        .map(|target| match target.split_once('=') {
+            Some((target, level)) => parse_level(target.trim(), level.trim()),
+            None => Ok((target.to_string(), LevelFilter::TRACE)),
+        })
+        .collect()
+}
+
+pub(crate) fn parse_level(target: &str, level: &str) -> Result<(String, LevelFilter), String> {
+    LevelFilter::from_str(level)
+        .map(|level| (target.to_string(), level))
+        .map_err(|_| format!("{level:?} is not a valid level for target {target:?}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_directives() {
+        let directives = parse(
+            " provider=MyProv; keyword=0x10;targets=myapp=debug, hyper=warn,other;format=cs;",
+        )
+        .unwrap();
+        assert_eq!(directives.provider_name, "MyProv");
+        assert_eq!(directives.default_keyword, Some(0x10));
+        assert_eq!(
+            directives.targets,
+            Some(vec![
+                ("myapp".to_string(), LevelFilter::DEBUG),
+                ("hyper".to_string(), LevelFilter::WARN),
+                ("other".to_string(), LevelFilter::TRACE),
+            ])
+        );
+        assert!(directives.common_schema);
+        assert!(directives.provider_id.is_none());
+
+        let directives =
+            parse("provider=Other;guid={f2b42d7b-3c4e-5d26-8a1f-123456789abc};keyword=16").unwrap();
+        assert_eq!(
+            directives.provider_id.map(|id| id.to_u128()),
+            Some(0xf2b42d7b_3c4e_5d26_8a1f_123456789abc)
+        );
+        assert_eq!(directives.default_keyword, Some(16));
+        assert!(!directives.common_schema);
+
+        assert!(parse("keyword=0x10").is_err());
+        assert!(parse("provider=MyProv;keyword=high").is_err());
+        assert!(parse("provider=MyProv;targets=myapp=loud").is_err());
+        assert!(parse("provider=MyProv;format=xml").is_err());
+        assert!(parse("provider=MyProv;colour=blue").is_err());
+        assert!(parse("provider=MyProv;verbose").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use tracing::{metadata::LevelFilter, Subscriber};
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+use crate::{
+    error::EtwError,
+    native::{CommonSchemaOutput, NormalOutput},
+    LayerBuilder, ReloadHandle,
+};
+
+/// A [LayerBuilder] whose output mode and target filters are chosen at runtime, rather than
+/// in code. Returned by [LayerBuilder::from_env] and [LayerBuilder::from_directives].
+///
+/// As the type of the layer depends on the output mode, [DynamicLayerBuilder::build]
+/// returns a boxed layer.
+pub struct DynamicLayerBuilder {
+    builder: DynamicOutput,
+    targets: Option<Vec<(String, LevelFilter)>>,
+}
+
+enum DynamicOutput {
+    Normal(LayerBuilder<NormalOutput>),
+    CommonSchema(LayerBuilder<CommonSchemaOutput>),
+}
+
+impl DynamicLayerBuilder {
+    pub(crate) fn new_normal(builder: LayerBuilder<NormalOutput>) -> Self {
+        DynamicLayerBuilder {
+            builder: DynamicOutput::Normal(builder),
+            targets: None,
+        }
+    }
+
+    pub(crate) fn new_common_schema(builder: LayerBuilder<CommonSchemaOutput>) -> Self {
+        DynamicLayerBuilder {
+            builder: DynamicOutput::CommonSchema(builder),
+            targets: None,
+        }
+    }
+
+    pub(crate) fn with_targets(mut self, targets: Option<Vec<(String, LevelFilter)>>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// True if the layer will log events in the Common Schema 4.0 mapping.
+    /// See [LayerBuilder::new_common_schema_events].
+    pub fn is_common_schema(&self) -> bool {
+        matches!(self.builder, DynamicOutput::CommonSchema(_))
+    }
+
+    /// Get a handle that can change the keywords and a target filter of the layer after it
+    /// is built. See [LayerBuilder::reload_handle].
+    pub fn reload_handle(&self) -> ReloadHandle {
+        match &self.builder {
+            DynamicOutput::Normal(builder) => builder.reload_handle(),
+            DynamicOutput::CommonSchema(builder) => builder.reload_handle(),
+        }
+    }
+
+    /// Change the builder with `f` if the layer will log events normally, for settings that
+    /// cannot be given as directives.
+    ///
+    /// ```
+    /// let builder = tracing_etw::LayerBuilder::from_directives("provider=SampleProviderName")
+    ///     .unwrap()
+    ///     .configure_normal(|builder| builder.with_max_event_size(4096));
+    /// ```
+    pub fn configure_normal<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(LayerBuilder<NormalOutput>) -> LayerBuilder<NormalOutput>,
+    {
+        if let DynamicOutput::Normal(builder) = self.builder {
+            self.builder = DynamicOutput::Normal(f(builder));
+        }
+        self
+    }
+
+    /// Change the builder with `f` if the layer will log events in the Common Schema 4.0
+    /// mapping, for settings that cannot be given as directives.
+    pub fn configure_common_schema<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(LayerBuilder<CommonSchemaOutput>) -> LayerBuilder<CommonSchemaOutput>,
+    {
+        if let DynamicOutput::CommonSchema(builder) = self.builder {
+            self.builder = DynamicOutput::CommonSchema(f(builder));
+        }
+        self
+    }
+
+    /// Constructs the configured layer. If targets were given, the layer is built with
+    /// [LayerBuilder::build_with_targets], and otherwise with [LayerBuilder::build].
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// let built_layer = tracing_etw::LayerBuilder::from_directives(
+    ///     "provider=SampleProviderName;targets=myapp=debug,hyper=warn;format=cs",
+    /// )
+    /// .unwrap()
+    /// .build();
+    /// assert!(built_layer.is_ok());
+    /// # tracing_subscriber::registry().with(built_layer.unwrap());
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(not(feature = "global_filter"))))]
+    #[cfg(any(not(feature = "global_filter"), docsrs))]
+    pub fn build<S>(self) -> Result<Box<dyn Layer<S> + Send + Sync>, EtwError>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    {
+        match (self.builder, self.targets) {
+            (DynamicOutput::Normal(builder), None) => Ok(Box::new(builder.build()?)),
+            (DynamicOutput::Normal(builder), Some(targets)) => {
+                Ok(Box::new(builder.build_with_targets(targets)?))
+            }
+            (DynamicOutput::CommonSchema(builder), None) => Ok(Box::new(builder.build()?)),
+            (DynamicOutput::CommonSchema(builder), Some(targets)) => {
+                Ok(Box::new(builder.build_with_targets(targets)?))
+            }
+        }
+    }
+
+    /// Constructs the configured layer with [LayerBuilder::build_global_filter].
+    /// If targets were given, the layer only logs events from those targets and the provider
+    /// name, as with [LayerBuilder::build_with_targets].
+    #[cfg_attr(docsrs, doc(cfg(feature = "global_filter")))]
+    #[cfg(any(feature = "global_filter", docsrs))]
+    pub fn build_global_filter<S>(self) -> Result<Box<dyn Layer<S> + Send + Sync>, EtwError>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    {
+        match self.builder {
+            DynamicOutput::Normal(builder) => {
+                if let Some(targets) = self.targets {
+                    builder.set_target_filter(builder.build_target_filter(targets));
+                }
+                Ok(Box::new(builder.build_global_filter()?))
+            }
+            DynamicOutput::CommonSchema(builder) => {
+                if let Some(targets) = self.targets {
+                    builder.set_target_filter(builder.build_target_filter(targets));
+                }
+                Ok(Box::new(builder.build_global_filter()?))
+            }
+        }
+    }
+}
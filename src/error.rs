@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::MetadataIssue;
+
 #[derive(Error, Debug)]
 pub enum EtwError {
     #[error("Provider group GUID must not be zeros")]
@@ -10,4 +12,22 @@ pub enum EtwError {
     InvalidProviderNameCharacters(String),
     #[error("Linux provider name and provider group must less than 234 characters combined. Current length: {0:?}")]
     TooManyCharacters(usize),
+    #[error("Invalid etw_event! metadata: {}", display_issues(.0))]
+    InvalidEventMetadata(Vec<MetadataIssue>),
+    #[error("Events {1:?} and {2:?} both have event ID {0}")]
+    EventIdCollision(u16, String, String),
+    #[error("Environment variable {0:?} is not set")]
+    MissingEnvironmentVariable(String),
+    #[error("Invalid layer directive: {0}")]
+    InvalidDirective(String),
+    #[error("Invalid layer configuration: {0}")]
+    InvalidConfig(String),
+}
+
+fn display_issues(issues: &[MetadataIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| issue.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
 }
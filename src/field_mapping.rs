@@ -0,0 +1,141 @@
+/// How a field is written to a Common Schema event.
+///
+/// Field mappings are registered with
+/// [LayerBuilder::with_field_mapping][crate::LayerBuilder::with_field_mapping].
+/// Fields without a mapping are written to PartC with their original name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum FieldMapping {
+    /// Write the field to PartC with a different name.
+    Rename(String),
+    /// Leave the field out of the event entirely.
+    Drop,
+    /// Write the field to PartB, with the given name, instead of PartC.
+    ///
+    /// PartB already contains `_typeName`, `name`, and either `eventTime` (events)
+    /// or `parentId` and `startTime` (spans). Building a layer with a field moved into
+    /// PartB with one of these names fails with
+    /// [EtwError::InvalidConfig][crate::error::EtwError::InvalidConfig].
+    PartB(String),
+}
+
+// The names of the fields this crate always writes to PartB
+const RESERVED_PART_B_NAMES: [&str; 5] =
+    ["_typeName", "name", "eventTime", "parentId", "startTime"];
+
+// The Common Schema struct a field is written to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum CommonSchemaPart {
+    PartB,
+    PartC,
+}
+
+// The field mappings for a single layer
+#[derive(Clone)]
+pub(crate) struct FieldMappings {
+    rules: Vec<(Box<str>, FieldMapping)>,
+}
+
+impl Default for FieldMappings {
+    // The `message` field that `tracing` adds to events is written as `Body`,
+    // unless a different mapping is registered for it.
+    fn default() -> Self {
+        FieldMappings {
+            rules: vec![("message".into(), FieldMapping::Rename("Body".to_string()))],
+        }
+    }
+}
+
+impl FieldMappings {
+    pub(crate) fn add(&mut self, field_name: &str, mapping: FieldMapping) {
+        if let Some(rule) = self.rules.iter_mut().find(|(n, _)| &**n == field_name) {
+            rule.1 = mapping;
+        } else {
+            self.rules.push((field_name.into(), mapping));
+        }
+    }
+
+    // Where a field is written and under what name, or None if the field is dropped
+    pub(crate) fn place<'a>(&'a self, field_name: &'a str) -> Option<(CommonSchemaPart, &'a str)> {
+        let mapping = self
+            .rules
+            .iter()
+            .find(|(name, _)| &**name == field_name)
+            .map(|(_, mapping)| mapping);
+
+        match mapping {
+            None => Some((CommonSchemaPart::PartC, field_name)),
+            Some(FieldMapping::Rename(name)) => Some((CommonSchemaPart::PartC, name)),
+            Some(FieldMapping::Drop) => None,
+            Some(FieldMapping::PartB(name)) => Some((CommonSchemaPart::PartB, name)),
+        }
+    }
+
+    pub(crate) fn is_in(&self, field_name: &str, part: CommonSchemaPart) -> bool {
+        matches!(self.place(field_name), Some((p, _)) if p == part)
+    }
+
+    // The first mapping that moves a field into PartB with the name of a field that is already there
+    pub(crate) fn reserved_part_b_mapping(&self) -> Option<(&str, &str)> {
+        self.rules
+            .iter()
+            .find_map(|(field_name, mapping)| match mapping {
+                FieldMapping::PartB(name) if RESERVED_PART_B_NAMES.contains(&name.as_str()) => {
+                    Some((&**field_name, name.as_str()))
+                }
+                _ => None,
+            })
+    }
+
+    // The number of the given fields that are written to PartB and to PartC
+    pub(crate) fn count_fields<'n>(&self, field_names: impl Iterator<Item = &'n str>) -> (u8, u8) {
+        field_names.fold((0u8, 0u8), |(b, c), name| match self.place(name) {
+            Some((CommonSchemaPart::PartB, _)) => (b.saturating_add(1), c),
+            Some((CommonSchemaPart::PartC, _)) => (b, c.saturating_add(1)),
+            None => (b, c),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn message_is_body_by_default() {
+        let mut mappings = FieldMappings::default();
+        assert_eq!(
+            mappings.place("message"),
+            Some((CommonSchemaPart::PartC, "Body"))
+        );
+        assert_eq!(
+            mappings.place("other"),
+            Some((CommonSchemaPart::PartC, "other"))
+        );
+
+        mappings.add("message", FieldMapping::PartB("body".to_string()));
+        mappings.add("password", FieldMapping::Drop);
+        assert_eq!(
+            mappings.place("message"),
+            Some((CommonSchemaPart::PartB, "body"))
+        );
+        assert_eq!(mappings.place("password"), None);
+        assert!(!mappings.is_in("password", CommonSchemaPart::PartC));
+
+        assert_eq!(
+            mappings.count_fields(["message", "password", "other"].into_iter()),
+            (1, 1)
+        );
+
+        assert_eq!(mappings.reserved_part_b_mapping(), None);
+        mappings.add("time", FieldMapping::PartB("eventTime".to_string()));
+        assert_eq!(
+            mappings.reserved_part_b_mapping(),
+            Some(("time", "eventTime"))
+        );
+    }
+}
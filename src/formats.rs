@@ -0,0 +1,235 @@
+//! Wrapper types that attach a semantic format to a field value.
+//!
+//! ETW and user_events both let each field carry a format hint that tells decoders
+//! such as WPA or `decode-perf` how to display the value, for example as a hex number
+//! or an IP address. `tracing` has no way to express this, so by default every integer
+//! is logged with the default (decimal) format.
+//!
+//! Wrapping a value in one of these types and logging it with the [Debug][std::fmt::Debug]
+//! (`?`) or [Display][std::fmt::Display] (`%`) specifier logs the value with the matching
+//! format instead of as a string. Other layers will see the value formatted as text.
+//!
+//! ```
+//! use std::net::Ipv4Addr;
+//! use tracing::{event, Level};
+//! use tracing_etw::formats::{Hex, Ipv4};
+//!
+//! event!(Level::INFO, flags = ?Hex(0x1f), peer = %Ipv4(Ipv4Addr::LOCALHOST), "Connected");
+//! ```
+//!
+//! | Wrapper | ETW (TraceLogging) | user_events (EventHeader) |
+//! |---------|--------------------|---------------------------|
+//! | [Hex] | `UINT64` + `HEX` | `Value64` + `HexInt` |
+//! | [Ipv4] | `UINT32` + `IPV4` | `Value32` + `IPAddress` |
+//! | [Ipv6] | `BINARY` + `IPV6` | `Value128` + `IPAddress` |
+//! | [Guid] | `GUID` | `Value128` + `Uuid` |
+//! | [Errno] | `INT32` | `Value32` + `Errno` |
+//! | [Pid] | `UINT32` + `PID` | `Value32` + `Pid` |
+//! | [Hresult] | `INT32` + `HRESULT` | `Value32` + `HexInt` |
+
+use std::{
+    cell::Cell,
+    fmt::{self, Write},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// Logs a `u64` as a hexadecimal number.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Hex(pub u64);
+
+/// Logs an IPv4 address.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4(pub Ipv4Addr);
+
+/// Logs an IPv6 address.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6(pub Ipv6Addr);
+
+/// Logs a `u128` as a GUID/UUID.
+///
+/// The value uses the same layout as [u128] conversions for
+/// [GuidWrapper][crate::native::GuidWrapper], i.e. `0x12345678_9abc_def0_...`
+/// is displayed as `12345678-9abc-def0-...`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub u128);
+
+/// Logs an `i32` as a POSIX `errno` value.
+///
+/// ETW has no errno format, so on Windows this is logged as a plain signed integer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Errno(pub i32);
+
+/// Logs a `u32` as a process ID.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Pid(pub u32);
+
+/// Logs an `i32` as a Windows `HRESULT`.
+///
+/// EventHeader has no HRESULT format, so on Linux this is logged as a hexadecimal integer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Hresult(pub i32);
+
+// The value of a format wrapper, as seen by the event encoders.
+#[doc(hidden)]
+#[derive(Clone, Copy)]
+pub enum FormattedValue {
+    Hex(u64),
+    Ipv4([u8; 4]),
+    Ipv6([u8; 16]),
+    Guid(u128),
+    Errno(i32),
+    Pid(u32),
+    Hresult(i32),
+}
+
+// `tracing` only hands a layer a `&dyn Debug` for these values, which cannot be downcast.
+// Instead, the wrappers leave their value here while being formatted, and the visitors
+// pick it back up once formatting finishes.
+#[derive(Clone, Copy)]
+struct PendingFormat {
+    count: u32,
+    len: usize,
+    value: Option<FormattedValue>,
+}
+
+thread_local! {
+    static PENDING_FORMAT: Cell<PendingFormat> = const {
+        Cell::new(PendingFormat {
+            count: 0,
+            len: 0,
+            value: None,
+        })
+    };
+}
+
+// Called by the visitors before formatting a Debug value.
+pub(crate) fn begin_format() {
+    PENDING_FORMAT.set(PendingFormat {
+        count: 0,
+        len: 0,
+        value: None,
+    });
+}
+
+// Called by the visitors after formatting a Debug value into `formatted_len` bytes.
+// Only returns a value if the whole output came from exactly one wrapper, so that
+// e.g. a `Vec<Hex>` or a struct containing a `Hex` is still logged as a string.
+pub(crate) fn end_format(formatted_len: usize) -> Option<FormattedValue> {
+    let pending = PENDING_FORMAT.replace(PendingFormat {
+        count: 0,
+        len: 0,
+        value: None,
+    });
+
+    if pending.count == 1 && pending.len == formatted_len {
+        pending.value
+    } else {
+        None
+    }
+}
+
+struct LenCounter(usize);
+
+impl Write for LenCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+fn write_formatted(
+    f: &mut fmt::Formatter<'_>,
+    value: FormattedValue,
+    args: fmt::Arguments<'_>,
+) -> fmt::Result {
+    let mut len = LenCounter(0);
+    let _ = len.write_fmt(args);
+
+    let pending = PENDING_FORMAT.get();
+    PENDING_FORMAT.set(PendingFormat {
+        count: pending.count.saturating_add(1),
+        len: len.0,
+        value: Some(value),
+    });
+
+    f.write_fmt(args)
+}
+
+macro_rules! impl_formatted {
+    ($ty:ident, |$v:ident| $value:expr, $($fmt:tt)+) => {
+        impl fmt::Debug for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let $v = self.0;
+                write_formatted(f, $value, format_args!($($fmt)+))
+            }
+        }
+
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(self, f)
+            }
+        }
+    };
+}
+
+impl_formatted!(Hex, |v| FormattedValue::Hex(v), "{:#x}", v);
+impl_formatted!(Ipv4, |v| FormattedValue::Ipv4(v.octets()), "{}", v);
+impl_formatted!(Ipv6, |v| FormattedValue::Ipv6(v.octets()), "{}", v);
+impl_formatted!(
+    Guid,
+    |v| FormattedValue::Guid(v),
+    "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+    (v >> 96) as u32,
+    (v >> 80) as u16,
+    (v >> 64) as u16,
+    (v >> 48) as u16,
+    v & 0xffff_ffff_ffff
+);
+impl_formatted!(Errno, |v| FormattedValue::Errno(v), "{}", v);
+impl_formatted!(Pid, |v| FormattedValue::Pid(v), "{}", v);
+impl_formatted!(
+    Hresult,
+    |v| FormattedValue::Hresult(v),
+    "{:#010x}",
+    v as u32
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format_one(value: &dyn fmt::Debug) -> (String, Option<FormattedValue>) {
+        begin_format();
+        let s = format!("{:?}", value);
+        let hint = end_format(s.len());
+        (s, hint)
+    }
+
+    #[test]
+    fn wrapper_is_recognised() {
+        let (s, hint) = format_one(&Hex(0x1f));
+        assert_eq!(s, "0x1f");
+        assert!(matches!(hint, Some(FormattedValue::Hex(0x1f))));
+
+        let (s, hint) = format_one(&Guid(0x12345678_9abc_def0_1122_334455667788));
+        assert_eq!(s, "12345678-9abc-def0-1122-334455667788");
+        assert!(matches!(hint, Some(FormattedValue::Guid(_))));
+
+        let (s, hint) = format_one(&Hresult(0x80004005u32 as i32));
+        assert_eq!(s, "0x80004005");
+        assert!(matches!(hint, Some(FormattedValue::Hresult(_))));
+    }
+
+    #[test]
+    fn nested_wrapper_is_a_string() {
+        let (s, hint) = format_one(&[Hex(1)]);
+        assert_eq!(s, "[0x1]");
+        assert!(hint.is_none());
+
+        let (_, hint) = format_one(&(Pid(1), Pid(2)));
+        assert!(hint.is_none());
+
+        let (_, hint) = format_one(&"not a wrapper");
+        assert!(hint.is_none());
+    }
+}
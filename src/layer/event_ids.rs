@@ -0,0 +1,90 @@
+use std::{hash::Hasher, sync::Arc};
+
+use hashers::fnv::FNV1aHasher64;
+
+// Gives etw_event! events that were not given an id: argument an ID from a hash of the provider
+// name and the event name, so that the ID of an event does not change between builds, or when
+// other events are added.
+#[derive(Clone)]
+pub(crate) struct HashedEventIds {
+    provider_name: Arc<str>,
+}
+
+impl HashedEventIds {
+    pub(crate) fn new(provider_name: &str) -> Self {
+        HashedEventIds {
+            provider_name: provider_name.into(),
+        }
+    }
+
+    pub(crate) fn id(&self, event_name: &str) -> u16 {
+        let mut hasher = FNV1aHasher64::default();
+        hasher.write(self.provider_name.as_bytes());
+        // Keeps e.g. "ab" + "c" and "a" + "bc" apart
+        hasher.write_u8(0);
+        hasher.write(event_name.as_bytes());
+        let hash = hasher.finish();
+
+        // ID 0 means the event has no ID
+        match (hash ^ (hash >> 16) ^ (hash >> 32) ^ (hash >> 48)) as u16 {
+            0 => 1,
+            id => id,
+        }
+    }
+}
+
+// The ID written in the header of an event whose id: argument is `explicit` (0 if it has none)
+#[inline]
+pub(crate) fn event_id(explicit: u16, event_name: &str, hashed: Option<&HashedEventIds>) -> u16 {
+    match hashed {
+        Some(hashed) if explicit == 0 => hashed.id(event_name),
+        _ => explicit,
+    }
+}
+
+// Finds two events with different names that would be written with the same ID, when IDs are
+// hashed. Events with the same name are expected to share their ID.
+pub(crate) fn find_collision<'a>(
+    events: impl Iterator<Item = (&'a str, u16)>,
+    hashed: &HashedEventIds,
+) -> Option<(u16, &'a str, &'a str)> {
+    let mut names = std::collections::HashMap::new();
+    for (name, explicit) in events {
+        let id = event_id(explicit, name, Some(hashed));
+        let first = *names.entry(id).or_insert(name);
+        if first != name {
+            return Some((id, first, name));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hashed_ids() {
+        let hashed = HashedEventIds::new("My.Provider");
+        let id = hashed.id("Db.Query");
+        assert_ne!(id, 0);
+        assert_eq!(HashedEventIds::new("My.Provider").id("Db.Query"), id);
+        assert_eq!(event_id(0, "Db.Query", Some(&hashed)), id);
+        assert_eq!(event_id(7, "Db.Query", Some(&hashed)), 7);
+        assert_eq!(event_id(0, "Db.Query", None), 0);
+
+        let events = [
+            ("Db.Query", 0),
+            ("Db.Query", 0),
+            ("Request", 5),
+            ("Other", 0),
+        ];
+        assert_eq!(find_collision(events.into_iter(), &hashed), None);
+
+        let events = [("Db.Query", 0), ("Request", id)];
+        assert_eq!(
+            find_collision(events.into_iter(), &hashed),
+            Some((id, "Db.Query", "Request"))
+        );
+    }
+}
@@ -3,7 +3,7 @@ use tracing_subscriber::{layer::Filter, registry::LookupSpan};
 
 use crate::{
     native::{OutputMode, ProviderTraits},
-    statics::get_event_metadata,
+    statics::find_event_metadata,
 };
 
 use super::EtwFilter;
@@ -16,11 +16,9 @@ where
         &self,
         metadata: &'static tracing::Metadata<'static>,
     ) -> tracing::subscriber::Interest {
-        let etw_meta = get_event_metadata(&metadata.callsite());
-        let keyword = if let Some(meta) = etw_meta {
-            meta.kw
-        } else {
-            self.layer.default_keyword
+        let etw_meta = find_event_metadata(&metadata.callsite());
+        let Some(keyword) = self.layer.filtered_keyword(metadata, etw_meta) else {
+            return tracing::subscriber::Interest::never();
         };
 
         if crate::native::Provider::<OutMode>::supports_enable_callback() {
@@ -41,8 +39,7 @@ where
         metadata: &tracing::Metadata<'_>,
         _cx: &tracing_subscriber::layer::Context<'_, S>,
     ) -> bool {
-        self.layer
-            .is_enabled(&metadata.callsite(), metadata.level())
+        self.layer.is_enabled(metadata)
     }
 
     fn event_enabled(
@@ -50,7 +47,6 @@ where
         event: &tracing::Event<'_>,
         _cx: &tracing_subscriber::layer::Context<'_, S>,
     ) -> bool {
-        self.layer
-            .is_enabled(&event.metadata().callsite(), event.metadata().level())
+        self.layer.is_enabled(event.metadata())
     }
 }
@@ -1,6 +1,4 @@
-use std::{
-    hash::{BuildHasher, Hash, Hasher}, sync::RwLock, time::SystemTime
-};
+use std::{hash::BuildHasher, sync::RwLock, time::SystemTime};
 
 use tracing::Subscriber;
 #[allow(unused_imports)] // Many imports are used exclusively by feature-gated code
@@ -33,16 +31,6 @@ impl BuildHasher for FNV1aHasher64HasherBuilder {
     fn build_hasher(&self) -> Self::Hasher {
         FNV1aHasher64::default()
     }
-
-    fn hash_one<T: Hash>(&self, x: T) -> u64
-    where
-        Self: Sized,
-        Self::Hasher: Hasher,
-    {
-        let mut hasher = self.build_hasher();
-        x.hash(&mut hasher);
-        hasher.finish()
-    }
 }
 
 struct SpanData {
@@ -70,11 +58,9 @@ where
         &self,
         metadata: &'static tracing::Metadata<'static>,
     ) -> tracing::subscriber::Interest {
-        let etw_meta = get_event_metadata(&metadata.callsite());
-        let keyword = if let Some(meta) = etw_meta {
-            meta.kw
-        } else {
-            self.layer.default_keyword
+        let etw_meta = find_event_metadata(&metadata.callsite());
+        let Some(keyword) = self.layer.filtered_keyword(metadata, etw_meta) else {
+            return tracing::subscriber::Interest::never();
         };
 
         if crate::native::Provider::<OutMode>::supports_enable_callback() {
@@ -96,8 +82,7 @@ where
         metadata: &tracing::Metadata<'_>,
         _ctx: tracing_subscriber::layer::Context<'_, S>,
     ) -> bool {
-        self.layer
-            .is_enabled(&metadata.callsite(), metadata.level())
+        self.layer.is_enabled(metadata)
     }
 
     #[cfg(any(feature = "global_filter", docsrs))]
@@ -106,8 +91,7 @@ where
         event: &tracing::Event<'_>,
         _ctx: tracing_subscriber::layer::Context<'_, S>,
     ) -> bool {
-        self.layer
-            .is_enabled(&event.metadata().callsite(), event.metadata().level())
+        self.layer.is_enabled(event.metadata())
     }
 
     fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
@@ -136,10 +120,21 @@ where
         let otel_context: Option<([u8; 32], [u8; 16])> = None;
 
         let etw_meta = get_event_metadata(&event.metadata().callsite());
-        let (name, keyword, tag) = if let Some(meta) = etw_meta {
-            (event.metadata().name(), meta.kw, meta.event_tag)
+        let (name, keyword, tag, options) = if let Some(meta) = etw_meta {
+            let mut options = meta.options;
+            options.id = event_ids::event_id(
+                options.id,
+                event.metadata().name(),
+                self.layer.hashed_event_ids.as_ref(),
+            );
+            (event.metadata().name(), meta.kw, meta.event_tag, options)
         } else {
-            (event.metadata().name(), self.layer.default_keyword, 0)
+            (
+                event.metadata().name(),
+                self.layer.default_keyword_for(event.metadata().target()),
+                0,
+                crate::_details::EventOptions::DEFAULT,
+            )
         };
 
         self.layer.provider.as_ref().write_record(
@@ -150,8 +145,10 @@ where
             event.metadata().level(),
             keyword,
             tag,
+            &options,
             event,
             otel_context,
+            &self.layer.payload,
         );
     }
 
@@ -169,11 +166,9 @@ where
 
         let metadata = span.metadata();
 
-        let parent_span_id = if attrs.is_contextual() {
-            attrs.parent().map_or(0, |id| id.into_u64())
-        } else {
-            0
-        };
+        // Only set for spans created with an explicit parent (e.g. with etw_span!'s parent:
+        // argument), as the parent of a contextual span is not in its attributes
+        let parent_span_id = attrs.parent().map_or(0, |id| id.into_u64());
 
         let n = metadata.fields().len();
 
@@ -224,6 +219,8 @@ where
 
         attrs.values().record(&mut SpanValueVisitor {
             fields: &mut data.fields,
+            max_value_len: self.layer.payload.max_span_value_size,
+            redactions: self.layer.payload.redactions.scope(metadata.target()),
         });
 
         // The tracing_subscriber::Registry guarantees that there will only ever be 1 span with a given ID
@@ -258,7 +255,7 @@ where
         let (keyword, tag) = if let Some(meta) = etw_meta {
             (meta.kw, meta.event_tag)
         } else {
-            (self.layer.default_keyword, 0)
+            (self.layer.default_keyword_for(metadata.target()), 0)
         };
 
         self.layer.provider.as_ref().span_start(
@@ -270,6 +267,7 @@ where
             metadata.level(),
             keyword,
             tag,
+            &self.layer.payload,
         );
 
         // TODO:
@@ -304,7 +302,7 @@ where
         let (keyword, tag) = if let Some(meta) = etw_meta {
             (meta.kw, meta.event_tag)
         } else {
-            (self.layer.default_keyword, 0)
+            (self.layer.default_keyword_for(metadata.target()), 0)
         };
 
         self.layer.provider.as_ref().span_stop(
@@ -316,12 +314,13 @@ where
             metadata.level(),
             keyword,
             tag,
+            &self.layer.payload,
         );
     }
 
     fn on_close(&self, id: span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
         let mut span_data_guard = SPAN_DATA.write().unwrap();
-        if let None = span_data_guard.remove(&id) {
+        if span_data_guard.remove(&id).is_none() {
             debug_assert!(false, "Close of unrecognized span");
         }
     }
@@ -350,6 +349,47 @@ where
 
         values.record(&mut SpanValueVisitor {
             fields: &mut data.fields,
+            max_value_len: self.layer.payload.max_span_value_size,
+            redactions: self
+                .layer
+                .payload
+                .redactions
+                .scope(span.metadata().target()),
+        });
+    }
+}
+
+#[cfg(all(test, not(feature = "global_filter")))]
+mod test {
+    use tracing::Level;
+    use tracing_subscriber::prelude::*;
+
+    use super::SPAN_DATA;
+    use crate::{etw_span, LayerBuilder};
+
+    // The ID of the span that the layer relates the span to, if any
+    fn related_span_id(span: &tracing::Span) -> Option<u64> {
+        let span_data = SPAN_DATA.read().unwrap();
+        let related = span_data[&span.id().unwrap()].related_activity_id;
+        (related[0] != 0).then(|| u64::from_le_bytes(related[8..].try_into().unwrap()))
+    }
+
+    // Uses keyword 0 so the sum in the statics tests is unaffected
+    #[test]
+    fn explicit_parent() {
+        let subscriber = tracing_subscriber::registry()
+            .with(LayerBuilder::new("ParentTests").__build_for_test().unwrap());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::span!(Level::INFO, "request");
+            let query = etw_span!(name: "Db.Query", parent: &request, Level::INFO, 0, rows = 1);
+            let idle = etw_span!(name: "Db.Idle", parent: None, Level::INFO, 0);
+
+            assert_eq!(
+                related_span_id(&query),
+                Some(request.id().unwrap().into_u64())
+            );
+            assert_eq!(related_span_id(&idle), None);
         });
     }
 }
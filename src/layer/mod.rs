@@ -1,4 +1,7 @@
+mod event_ids;
 mod layer_impl;
+mod reload;
+mod target_keywords;
 
 #[cfg(any(not(feature = "global_filter"), docsrs))]
 mod filter;
@@ -6,17 +9,25 @@ mod filter;
 use std::{marker::PhantomData, pin::Pin, sync::Arc};
 
 use tracing::Subscriber;
-use tracing_core::callsite;
 use tracing_subscriber::registry::LookupSpan;
 
 use crate::{
+    _details::EventMetadata,
     native::{OutputMode, ProviderTraits},
     statics::get_event_metadata,
+    values::PayloadConfig,
 };
 
+pub(crate) use event_ids::{find_collision, HashedEventIds};
+pub use reload::ReloadHandle;
+pub(crate) use reload::{LayerSettings, SharedSettings};
+pub(crate) use target_keywords::TargetKeywords;
+
 pub(crate) struct _EtwLayer<S, OutMode: OutputMode> {
     pub(crate) provider: Pin<Arc<crate::native::Provider<OutMode>>>,
-    pub(crate) default_keyword: u64,
+    pub(crate) settings: SharedSettings,
+    pub(crate) hashed_event_ids: Option<HashedEventIds>,
+    pub(crate) payload: Arc<PayloadConfig>,
     pub(crate) _p: PhantomData<S>,
 }
 
@@ -24,7 +35,9 @@ impl<S, OutMode: OutputMode> Clone for _EtwLayer<S, OutMode> {
     fn clone(&self) -> Self {
         _EtwLayer {
             provider: self.provider.clone(),
-            default_keyword: self.default_keyword,
+            settings: self.settings.clone(),
+            hashed_event_ids: self.hashed_event_ids.clone(),
+            payload: self.payload.clone(),
             _p: PhantomData,
         }
     }
@@ -43,18 +56,40 @@ pub struct EtwFilter<S, OutMode: OutputMode> {
     pub(crate) layer: _EtwLayer<S, OutMode>,
 }
 
+impl<S, OutMode: OutputMode> _EtwLayer<S, OutMode> {
+    // The keyword for an event or span that was not created with etw_event! or etw_span!
+    #[inline]
+    pub(crate) fn default_keyword_for(&self, target: &str) -> u64 {
+        self.settings.load().default_keyword_for(target)
+    }
+
+    // The keyword of an event or span, or None if it is disabled by the target filter set with
+    // a ReloadHandle
+    #[inline]
+    pub(crate) fn filtered_keyword(
+        &self,
+        metadata: &tracing::Metadata<'_>,
+        etw_meta: Option<&EventMetadata>,
+    ) -> Option<u64> {
+        let settings = self.settings.load();
+        if !settings.targets_enable(metadata) {
+            return None;
+        }
+
+        if let Some(meta) = etw_meta {
+            Some(meta.kw)
+        } else {
+            Some(settings.default_keyword_for(metadata.target()))
+        }
+    }
+}
+
 impl<S, OutMode: OutputMode> _EtwLayer<S, OutMode>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    fn is_enabled(&self, callsite: &callsite::Identifier, level: &tracing_core::Level) -> bool {
-        let etw_meta = get_event_metadata(callsite);
-        let keyword = if let Some(meta) = etw_meta {
-            meta.kw
-        } else {
-            self.default_keyword
-        };
-
-        self.provider.enabled(level, keyword)
+    fn is_enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+        self.filtered_keyword(metadata, get_event_metadata(&metadata.callsite()))
+            .is_some_and(|keyword| self.provider.enabled(metadata.level(), keyword))
     }
 }
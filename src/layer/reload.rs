@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use tracing_subscriber::filter::Targets;
+
+use super::TargetKeywords;
+
+// The settings of a layer that can be changed after it is built, through a ReloadHandle.
+// The settings are replaced as a whole on each change, so the layer can read them without a lock.
+pub(crate) type SharedSettings = Arc<ArcSwap<LayerSettings>>;
+
+#[derive(Clone, Default)]
+pub(crate) struct LayerSettings {
+    pub(crate) default_keyword: u64,
+    pub(crate) target_keywords: TargetKeywords,
+    pub(crate) targets: Option<Targets>,
+}
+
+impl LayerSettings {
+    // The keyword for an event or span that was not created with etw_event! or etw_span!
+    #[inline]
+    pub(crate) fn default_keyword_for(&self, target: &str) -> u64 {
+        self.target_keywords
+            .get(target)
+            .unwrap_or(self.default_keyword)
+    }
+
+    #[inline]
+    pub(crate) fn targets_enable(&self, metadata: &tracing::Metadata<'_>) -> bool {
+        self.targets
+            .as_ref()
+            .is_none_or(|targets| targets.would_enable(metadata.target(), metadata.level()))
+    }
+}
+
+/// A handle to change the keywords and target filters of a layer after it is built, such as
+/// to re-route events during an incident without restarting the process.
+///
+/// Get a handle with [LayerBuilder::reload_handle][crate::LayerBuilder::reload_handle]
+/// before building the layer. Handles can be cloned and shared between threads. Whether each
+/// callsite is enabled is recalculated after every change, as with
+/// [tracing_core::callsite::rebuild_interest_cache].
+#[derive(Clone)]
+pub struct ReloadHandle {
+    settings: SharedSettings,
+}
+
+impl ReloadHandle {
+    pub(crate) fn new(settings: SharedSettings) -> Self {
+        ReloadHandle { settings }
+    }
+
+    /// Change the keyword used for events that do not explicitly set a keyword.
+    /// See [LayerBuilder::with_default_keyword][crate::LayerBuilder::with_default_keyword].
+    pub fn set_default_keyword(&self, keyword: u64) {
+        self.update(|settings| settings.default_keyword = keyword);
+    }
+
+    /// Replace the keywords used for events and spans with the given targets.
+    /// Targets that are not given go back to using the default keyword.
+    /// See [LayerBuilder::with_target_keywords][crate::LayerBuilder::with_target_keywords].
+    pub fn set_target_keywords<I, T>(&self, keywords: I)
+    where
+        I: IntoIterator<Item = (T, u64)>,
+        T: AsRef<str>,
+    {
+        let mut target_keywords = TargetKeywords::default();
+        for (target, keyword) in keywords {
+            target_keywords.add(target.as_ref(), keyword);
+        }
+        self.update(|settings| settings.target_keywords = target_keywords.clone());
+    }
+
+    /// Only log the events and spans that the given filter enables. This replaces the target
+    /// filter the layer was built with (e.g. with
+    /// [LayerBuilder::build_with_targets][crate::LayerBuilder::build_with_targets]),
+    /// or any filter that was set before, so targets can be raised as well as lowered.
+    ///
+    /// ```
+    /// # use tracing::Level;
+    /// # use tracing_subscriber::{filter::Targets, prelude::*};
+    /// let builder = tracing_etw::LayerBuilder::new("SampleProviderName");
+    /// let handle = builder.reload_handle();
+    /// let built_layer = builder.build();
+    /// assert!(built_layer.is_ok());
+    /// # let _subscriber = tracing_subscriber::registry().with(built_layer.unwrap());
+    ///
+    /// // ...
+    ///
+    /// handle.set_target_filter(Targets::new().with_target("myapp::db", Level::TRACE));
+    /// handle.set_default_keyword(0x10);
+    /// ```
+    pub fn set_target_filter(&self, targets: Targets) {
+        self.update(|settings| settings.targets = Some(targets.clone()));
+    }
+
+    /// Remove the target filter, including one the layer was built with, so that events and
+    /// spans from every target are logged.
+    pub fn clear_target_filter(&self) {
+        self.update(|settings| settings.targets = None);
+    }
+
+    // f may be called more than once, if another handle changes the settings at the same time
+    fn update(&self, f: impl Fn(&mut LayerSettings)) {
+        self.settings.rcu(|current| {
+            let mut settings = LayerSettings::clone(current);
+            f(&mut settings);
+            settings
+        });
+        tracing_core::callsite::rebuild_interest_cache();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tracing::Level;
+
+    use crate::LayerBuilder;
+
+    #[test]
+    fn reload_settings() {
+        let builder = LayerBuilder::new("ReloadTest")
+            .with_default_keyword(0x2)
+            .with_target_keywords([("myapp::db", 0x4)]);
+        let handle = builder.reload_handle();
+        let layer = builder
+            .__build_for_test::<tracing_subscriber::Registry>()
+            .unwrap()
+            .layer;
+
+        assert_eq!(layer.default_keyword_for("myapp"), 0x2);
+        assert_eq!(layer.default_keyword_for("myapp::db::pool"), 0x4);
+
+        handle.set_default_keyword(0x8);
+        handle.set_target_keywords([("myapp", 0x10)]);
+        assert_eq!(layer.default_keyword_for("other"), 0x8);
+        assert_eq!(layer.default_keyword_for("myapp::db::pool"), 0x10);
+
+        static CALLSITE: tracing::callsite::DefaultCallsite =
+            tracing::callsite::DefaultCallsite::new(&META);
+        static META: tracing::Metadata<'static> = tracing::Metadata::new(
+            "event",
+            "myapp::db",
+            Level::DEBUG,
+            None,
+            None,
+            None,
+            tracing::field::FieldSet::new(&[], tracing_core::identify_callsite!(&CALLSITE)),
+            tracing::metadata::Kind::EVENT,
+        );
+        let enabled = || layer.settings.load().targets_enable(&META);
+        let targets = tracing_subscriber::filter::Targets::new;
+
+        assert!(enabled());
+        handle.set_target_filter(targets().with_target("myapp", Level::INFO));
+        assert!(!enabled());
+        handle.set_target_filter(targets().with_target("myapp::db", Level::DEBUG));
+        assert!(enabled());
+        handle.set_target_filter(targets().with_target("myapp", Level::INFO));
+        handle.clear_target_filter();
+        assert!(enabled());
+    }
+}
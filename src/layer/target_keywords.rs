@@ -0,0 +1,53 @@
+use crate::redaction::target_matches;
+
+// The keywords for events and spans without etw_event! or etw_span! metadata, by target.
+// As with target redaction rules, a target also matches any module path nested within it,
+// and the longest matching target wins.
+#[derive(Clone, Default)]
+pub(crate) struct TargetKeywords {
+    targets: Vec<(Box<str>, u64)>,
+}
+
+impl TargetKeywords {
+    pub(crate) fn add(&mut self, target: &str, keyword: u64) {
+        if let Some(rule) = self.targets.iter_mut().find(|(t, _)| &**t == target) {
+            rule.1 = keyword;
+        } else {
+            self.targets.push((target.into(), keyword));
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, target: &str) -> Option<u64> {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| target_matches(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, keyword)| *keyword)
+    }
+
+    pub(crate) fn keywords(&self) -> impl Iterator<Item = u64> + '_ {
+        self.targets.iter().map(|(_, keyword)| *keyword)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn longest_target_wins() {
+        let mut keywords = TargetKeywords::default();
+        keywords.add("myapp", 0x1);
+        keywords.add("myapp::db", 0x10);
+        keywords.add("hyper", 0x20);
+        keywords.add("myapp", 0x2);
+
+        assert_eq!(keywords.get("myapp"), Some(0x2));
+        assert_eq!(keywords.get("myapp::net"), Some(0x2));
+        assert_eq!(keywords.get("myapp::db::pool"), Some(0x10));
+        assert_eq!(keywords.get("hyper::proto"), Some(0x20));
+        assert_eq!(keywords.get("hyperlocal"), None);
+        assert_eq!(keywords.keywords().collect::<Vec<_>>(), [0x2, 0x10, 0x20]);
+    }
+}
@@ -6,18 +6,26 @@ use std::{pin::Pin, sync::Arc};
 use tracing::metadata::LevelFilter;
 use tracing::Subscriber;
 #[allow(unused_imports)]
-use tracing_subscriber::filter::{combinator::And, FilterExt, Filtered, Targets};
+use tracing_subscriber::filter::{Filtered, Targets};
 use tracing_subscriber::registry::LookupSpan;
 #[allow(unused_imports)]
 use tracing_subscriber::{layer::Filter, Layer};
 
+use crate::directives::Directives;
+use crate::dynamic_layer_builder::DynamicLayerBuilder;
 use crate::error::EtwError;
+use crate::field_mapping::FieldMapping;
 #[cfg(any(not(feature = "global_filter"), docsrs))]
 use crate::layer::EtwFilter;
-use crate::layer::{EtwLayer, _EtwLayer};
+use crate::layer::{
+    _EtwLayer, EtwLayer, HashedEventIds, LayerSettings, ReloadHandle, SharedSettings,
+    TargetKeywords,
+};
 use crate::native::{
     CommonSchemaOutput, EventWriter, GuidWrapper, NormalOutput, OutputMode, ProviderTraits,
 };
+use crate::redaction::Redaction;
+use crate::values::PayloadConfig;
 
 /// Builds a [tracing_subscriber::Layer] that will logs events from a single
 /// ETW or user_events provider. Use [LayerBuilder::new] to construct a new
@@ -39,6 +47,11 @@ pub struct LayerBuilder<OutMode: OutputMode> {
     provider_id: GuidWrapper,
     provider_group: Option<crate::native::ProviderGroupType>,
     default_keyword: u64,
+    target_keywords: TargetKeywords,
+    payload: PayloadConfig,
+    validate_metadata: bool,
+    hashed_event_ids: bool,
+    settings: SharedSettings,
     _o: PhantomData<OutMode>,
 }
 
@@ -64,9 +77,114 @@ impl LayerBuilder<NormalOutput> {
             provider_id: GuidWrapper::from_name(name),
             provider_group: None,
             default_keyword: 1,
+            target_keywords: TargetKeywords::default(),
+            payload: PayloadConfig::default(),
+            validate_metadata: false,
+            hashed_event_ids: false,
+            settings: Arc::default(),
             _o: PhantomData,
         }
     }
+
+    /// Creates a new layer builder from the directives in the given environment variable,
+    /// so that the provider can be configured without a rebuild.
+    /// See [LayerBuilder::from_directives] for the format of the directives.
+    ///
+    /// An error is returned if the variable is not set, or its directives are not valid.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # std::env::set_var("TRACING_ETW", "provider=SampleProviderName;keyword=0x10");
+    /// let built_layer = tracing_etw::LayerBuilder::from_env("TRACING_ETW")
+    ///     .unwrap_or_else(|_| {
+    ///         tracing_etw::LayerBuilder::from_directives("provider=SampleProviderName").unwrap()
+    ///     })
+    ///     .build();
+    /// assert!(built_layer.is_ok());
+    /// # tracing_subscriber::registry().with(built_layer.unwrap());
+    /// ```
+    ///
+    pub fn from_env(name: &str) -> Result<DynamicLayerBuilder, EtwError> {
+        let directives = std::env::var(name)
+            .map_err(|_| EtwError::MissingEnvironmentVariable(name.to_string()))?;
+        Self::from_directives(&directives)
+    }
+
+    /// Creates a new layer builder from a list of `key=value` directives separated by `;`,
+    /// such as `provider=MyProv;group=mygroup;keyword=0x10;targets=myapp=debug,hyper=warn;format=cs`.
+    ///
+    /// - `provider`: The provider name, as given to [LayerBuilder::new]. Required.
+    /// - `guid`: The provider ID, as given to [LayerBuilder::with_provider_id].
+    /// - `group`: The provider group, as given to [LayerBuilder::with_provider_group].
+    ///   For ETW, this is a GUID.
+    /// - `keyword`: The default keyword, in decimal or hex with a `0x` prefix, as given to
+    ///   [LayerBuilder::with_default_keyword].
+    /// - `targets`: A comma separated list of targets to build the layer with, each optionally
+    ///   followed by `=level` (e.g. `debug`, or `off`). See [LayerBuilder::build_with_targets].
+    ///   Targets without a level are enabled at every level.
+    /// - `format`: `normal`, or `cs` to log events in the Common Schema 4.0 mapping
+    ///   (see [LayerBuilder::new_common_schema_events]).
+    ///
+    /// ```
+    /// let builder = tracing_etw::LayerBuilder::from_directives(
+    ///     "provider=SampleProviderName;keyword=0x10;format=cs",
+    /// )
+    /// .unwrap();
+    /// assert!(builder.is_common_schema());
+    /// ```
+    ///
+    pub fn from_directives(directives: &str) -> Result<DynamicLayerBuilder, EtwError> {
+        let directives =
+            crate::directives::parse(directives).map_err(EtwError::InvalidDirective)?;
+
+        Ok(Self::from_parsed_directives(directives))
+    }
+
+    /// Creates a new layer builder from a [LayerConfig][crate::LayerConfig], such as one
+    /// deserialized from a configuration file.
+    ///
+    /// An error is returned if the provider ID, provider group, or a target level is not
+    /// valid, or if field mappings are given for a layer that does not use Common Schema output.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// let mut config = tracing_etw::LayerConfig::new("SampleProviderName");
+    /// config.default_keyword = Some(0x10);
+    /// config.field_redactions.insert("password".to_string(), tracing_etw::Redaction::Drop);
+    ///
+    /// let built_layer = tracing_etw::LayerBuilder::from_config(&config)
+    ///     .unwrap()
+    ///     .build();
+    /// assert!(built_layer.is_ok());
+    /// # tracing_subscriber::registry().with(built_layer.unwrap());
+    /// ```
+    ///
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    #[cfg(feature = "serde")]
+    pub fn from_config(config: &crate::LayerConfig) -> Result<DynamicLayerBuilder, EtwError> {
+        let directives = config.to_directives().map_err(EtwError::InvalidConfig)?;
+
+        Ok(Self::from_parsed_directives(directives)
+            .configure_normal(|builder| config.apply_rules(builder))
+            .configure_common_schema(|builder| {
+                config.apply_field_mappings(config.apply_rules(builder))
+            }))
+    }
+
+    fn from_parsed_directives(directives: Directives) -> DynamicLayerBuilder {
+        let builder = if directives.common_schema {
+            DynamicLayerBuilder::new_common_schema(
+                LayerBuilder::new_common_schema_events(&directives.provider_name)
+                    .with_directives(&directives),
+            )
+        } else {
+            DynamicLayerBuilder::new_normal(
+                LayerBuilder::new(&directives.provider_name).with_directives(&directives),
+            )
+        };
+
+        builder.with_targets(directives.targets)
+    }
 }
 
 impl LayerBuilder<CommonSchemaOutput> {
@@ -94,9 +212,44 @@ impl LayerBuilder<CommonSchemaOutput> {
             provider_id: GuidWrapper::from_name(name),
             provider_group: None,
             default_keyword: 1,
+            target_keywords: TargetKeywords::default(),
+            payload: PayloadConfig::default(),
+            validate_metadata: false,
+            hashed_event_ids: false,
+            settings: Arc::default(),
             _o: PhantomData,
         }
     }
+
+    /// Change how a field is written to Common Schema events and spans:
+    /// rename it, drop it, or move it from PartC into PartB.
+    ///
+    /// This allows events to match the column names that downstream consumers expect
+    /// without changing the instrumentation. Calling this again with the same field
+    /// name replaces the previous mapping.
+    ///
+    /// If no other mapping is set for it, the `message` field is renamed to `Body`.
+    /// Building the layer fails if a field is moved into PartB with the name of a field
+    /// that is always in PartB (see [FieldMapping::PartB]).
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// # let layer =
+    /// tracing_etw::LayerBuilder::new_common_schema_events("SampleProviderName")
+    ///     .with_field_mapping("message", tracing_etw::FieldMapping::PartB("body".to_string()))
+    ///     .with_field_mapping("req_id", tracing_etw::FieldMapping::Rename("RequestId".to_string()))
+    ///     .with_field_mapping("internal", tracing_etw::FieldMapping::Drop)
+    /// # ;
+    /// # let built = layer.build();
+    /// # assert!(built.is_ok());
+    /// # reg.with(built.unwrap());
+    /// ```
+    ///
+    pub fn with_field_mapping(mut self, field_name: &str, mapping: FieldMapping) -> Self {
+        self.payload.field_mappings.add(field_name, mapping);
+        self
+    }
 }
 
 impl<OutMode: OutputMode + 'static> LayerBuilder<OutMode> {
@@ -145,9 +298,11 @@ impl<OutMode: OutputMode + 'static> LayerBuilder<OutMode> {
         self.provider_id
     }
 
-    /// Set the keyword used for events that do not explicitly set a keyword.
+    /// Set the keyword used for events that do not explicitly set a keyword,
+    /// and whose target does not have a keyword set with [LayerBuilder::with_target_keywords].
     ///
-    /// Events logged with the [crate::etw_event!] macro specify a keyword for the event.
+    /// Events logged with the [crate::etw_event!] macro and spans created with the
+    /// [crate::etw_span!] macro specify their own keyword.
     /// Events and spans logged with the [tracing::event!], [tracing::span!],
     /// or other similar `tracing` macros will use the default keyword.
     ///
@@ -179,6 +334,147 @@ impl<OutMode: OutputMode + 'static> LayerBuilder<OutMode> {
         self
     }
 
+    /// Set the keywords used for events and spans with the given targets, instead of
+    /// the [default keyword][LayerBuilder::with_default_keyword].
+    ///
+    /// This gives events logged with the [tracing::event!] and other `tracing` macros,
+    /// including events from other crates, a keyword without changing their callsites.
+    /// A target also matches any target nested within it (e.g. `myapp::db` also matches
+    /// `myapp::db::pool`), and if several targets match, the longest target wins.
+    /// Setting the keyword of a target again replaces the previous keyword.
+    ///
+    /// Events logged with the [crate::etw_event!] macro and spans created with the
+    /// [crate::etw_span!] macro always use their own keyword.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// # let layer =
+    /// tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .with_target_keywords([("myapp::db", 0x10), ("hyper", 0x20)])
+    /// # ;
+    /// # let built = layer.build();
+    /// # assert!(built.is_ok());
+    /// # reg.with(built.unwrap());
+    /// ```
+    ///
+    pub fn with_target_keywords<I, T>(mut self, keywords: I) -> Self
+    where
+        I: IntoIterator<Item = (T, u64)>,
+        T: AsRef<str>,
+    {
+        for (target, keyword) in keywords {
+            self.target_keywords.add(target.as_ref(), keyword);
+        }
+        self
+    }
+
+    /// Set the maximum number of bytes the fields of a single event may use.
+    ///
+    /// ETW and user_events drop events that are larger than 64KB. To keep one very large
+    /// value (usually a long [Debug][std::fmt::Debug]-formatted message) from causing the
+    /// whole event to be lost, string values that do not fit in the remaining budget are
+    /// truncated. When this happens, a `_truncated` struct is added to the end of the event
+    /// with one field per truncated value, holding that value's original length in bytes.
+    /// The `_truncated` struct is counted against the budget, and lists at most 127 values.
+    /// The same budget applies to the events logged when a span starts and stops.
+    ///
+    /// The size of each field is an estimate of its encoded size, including the field name.
+    /// A small amount of space is always reserved for the event name and other fields added
+    /// by this crate.
+    ///
+    /// If this method is not called, the budget will be 60KB.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// # let layer =
+    /// tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .with_max_event_size(16 * 1024)
+    /// # ;
+    /// # let built = layer.build();
+    /// # assert!(built.is_ok());
+    /// # reg.with(built.unwrap());
+    /// ```
+    ///
+    pub fn with_max_event_size(mut self, bytes: usize) -> Self {
+        self.payload.max_event_size = bytes;
+        self
+    }
+
+    /// Set the maximum length, in bytes, of each string value stored for a span.
+    ///
+    /// Span field values are kept in memory until the span closes so they can be logged
+    /// when the span is exited. Longer values are truncated when they are recorded.
+    ///
+    /// If this method is not called, the limit will be 4KB.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// # let layer =
+    /// tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .with_max_span_value_size(256)
+    /// # ;
+    /// # let built = layer.build();
+    /// # assert!(built.is_ok());
+    /// # reg.with(built.unwrap());
+    /// ```
+    ///
+    pub fn with_max_span_value_size(mut self, bytes: usize) -> Self {
+        self.payload.max_span_value_size = bytes;
+        self
+    }
+
+    /// Redact every field with the given name, in both events and spans, before it is logged.
+    ///
+    /// A rule for a field name takes precedence over a rule for the target of the event
+    /// or span (see [LayerBuilder::with_target_redaction]).
+    /// Calling this again with the same field name replaces the previous rule.
+    ///
+    /// Redaction only applies to this layer; other layers still see the original value.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// # let layer =
+    /// tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .with_field_redaction("email", tracing_etw::Redaction::Hash)
+    ///     .with_field_redaction("password", tracing_etw::Redaction::Drop)
+    /// # ;
+    /// # let built = layer.build();
+    /// # assert!(built.is_ok());
+    /// # reg.with(built.unwrap());
+    /// ```
+    ///
+    pub fn with_field_redaction(mut self, field_name: &str, redaction: Redaction) -> Self {
+        self.payload.redactions.add_field(field_name, redaction);
+        self
+    }
+
+    /// Redact every field of events and spans with the given target, or a target nested
+    /// within it (e.g. `myapp::auth` also matches `myapp::auth::login`).
+    ///
+    /// If several target rules match, the longest target wins.
+    /// Calling this again with the same target replaces the previous rule.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// # let layer =
+    /// tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .with_target_redaction("myapp::auth", tracing_etw::Redaction::Mask("***".to_string()))
+    /// # ;
+    /// # let built = layer.build();
+    /// # assert!(built.is_ok());
+    /// # reg.with(built.unwrap());
+    /// ```
+    ///
+    pub fn with_target_redaction(mut self, target: &str, redaction: Redaction) -> Self {
+        self.payload.redactions.add_target(target, redaction);
+        self
+    }
+
     /// For advanced scenarios.
     /// Set the provider group to join this provider to.
     ///
@@ -193,26 +489,181 @@ impl<OutMode: OutputMode + 'static> LayerBuilder<OutMode> {
         self
     }
 
-    fn validate_config(&self) -> Result<(), EtwError> {
-        crate::native::Provider::<OutMode>::is_valid_provider(&self.provider_name).and_then(|_| {
-            self.provider_group.as_ref().map_or_else(
-                || Ok(()),
-                |group| {
-                    crate::native::Provider::<OutMode>::is_valid_group(&self.provider_name, group)
-                },
-            )
-        })
+    /// Check the metadata of every `etw_event!` and `etw_span!` callsite in the binary
+    /// when the layer is built, and fail to build the layer if there are any problems.
+    ///
+    /// This catches events that would be missed by collection profiles, such as the same
+    /// event name being used with different keywords or levels. See [crate::MetadataIssue]
+    /// for the problems that are checked, and [crate::validate_event_metadata] to check
+    /// for them without failing the build (e.g. to log them as warnings). With
+    /// [LayerBuilder::with_hashed_event_ids], it also fails if two events would have the same ID.
+    ///
+    /// The check is not run unless this method is called. Some issues may be intentional,
+    /// such as an event with keyword 0 that is only meant to be collected by sessions that
+    /// do not filter on keywords. Those would stop a layer from being built if the check
+    /// always ran. Nor can they be reported as warnings, as this crate does not write
+    /// anything itself, and the layer that would log them is not installed yet.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// # let layer =
+    /// tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .with_event_metadata_validation()
+    /// # ;
+    /// # let built = layer.build();
+    /// # assert!(built.is_ok());
+    /// # reg.with(built.unwrap());
+    /// ```
+    ///
+    pub fn with_event_metadata_validation(mut self) -> Self {
+        self.validate_metadata = true;
+        self
     }
 
-    #[cfg(any(not(feature = "global_filter"), docsrs))]
-    fn build_target_filter(&self, target: &'static str) -> Targets {
-        let mut targets = Targets::new().with_target(&*self.provider_name, LevelFilter::TRACE);
+    /// Give each event logged with the [crate::etw_event!] macro without an `id:` argument
+    /// an ID that is a hash of the provider name and the event name, instead of ID 0.
+    ///
+    /// The ID is written in the event header (the event descriptor ID for ETW, and the
+    /// EventHeader ID for user_events), so that consumers can identify events without
+    /// comparing names. As it only depends on the provider and event names, the ID of an
+    /// event does not change between builds or as other events are added.
+    ///
+    /// Hashed IDs are 16 bits, so two events with different names can get the same ID:
+    /// the odds of any collision are about 1% with 40 events, 7% with 100 events,
+    /// and 50% with 300 events. With [LayerBuilder::with_event_metadata_validation], building
+    /// the layer fails with [EtwError::EventIdCollision] if two `etw_event!` events with
+    /// different names would have the same ID, whether it was hashed or given with `id:`.
+    /// Give one of the events an explicit `id:` to resolve a collision.
+    /// Events and spans logged with the [tracing::event!] and other `tracing` macros keep ID 0.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// # let layer =
+    /// tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .with_hashed_event_ids()
+    /// # ;
+    /// # let built = layer.build();
+    /// # assert!(built.is_ok());
+    /// # reg.with(built.unwrap());
+    /// ```
+    ///
+    pub fn with_hashed_event_ids(mut self) -> Self {
+        self.hashed_event_ids = true;
+        self
+    }
 
-        if !target.is_empty() {
-            targets = targets.with_target(target, LevelFilter::TRACE)
+    fn with_directives(mut self, directives: &Directives) -> Self {
+        if let Some(provider_id) = directives.provider_id {
+            self.provider_id = provider_id;
+        }
+        if let Some(group) = &directives.provider_group {
+            self.provider_group = Some(group.clone());
+        }
+        if let Some(keyword) = directives.default_keyword {
+            self.default_keyword = keyword;
         }
+        self
+    }
+
+    /// Get a handle that can change the default keyword, the keywords of targets, and the
+    /// target filter of the layer after it is built. See [ReloadHandle].
+    ///
+    /// The keywords set with this builder, and the targets given to
+    /// [LayerBuilder::build_with_targets], are used until they are changed with the handle,
+    /// and replace any set with the handle before the layer is built.
+    ///
+    /// ```
+    /// # use tracing_subscriber::prelude::*;
+    /// let builder = tracing_etw::LayerBuilder::new("SampleProviderName");
+    /// let handle = builder.reload_handle();
+    /// let built_layer = builder.build();
+    /// assert!(built_layer.is_ok());
+    /// # let _subscriber = tracing_subscriber::registry().with(built_layer.unwrap());
+    ///
+    /// // ...
+    ///
+    /// handle.set_target_keywords([("myapp::db", 0x20)]);
+    /// ```
+    ///
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle::new(self.settings.clone())
+    }
 
-        targets
+    fn hashed_ids(&self) -> Option<HashedEventIds> {
+        self.hashed_event_ids
+            .then(|| HashedEventIds::new(&self.provider_name))
+    }
+
+    fn validate_config(&self) -> Result<(), EtwError> {
+        crate::native::Provider::<OutMode>::is_valid_provider(&self.provider_name).and_then(
+            |_| {
+                self.provider_group.as_ref().map_or_else(
+                    || Ok(()),
+                    |group| {
+                        crate::native::Provider::<OutMode>::is_valid_group(
+                            &self.provider_name,
+                            group,
+                        )
+                    },
+                )
+            },
+        )?;
+
+        if let Some((field_name, name)) = self.payload.field_mappings.reserved_part_b_mapping() {
+            return Err(EtwError::InvalidConfig(format!(
+                "field {field_name:?} cannot be moved into PartB as {name:?}, which is already in PartB"
+            )));
+        }
+
+        if self.validate_metadata {
+            let issues = crate::validate_event_metadata();
+            if !issues.is_empty() {
+                return Err(EtwError::InvalidEventMetadata(issues));
+            }
+
+            // Explicit IDs are only checked along with hashed IDs, as events from other
+            // crates may use the same explicit IDs for other providers
+            if let Some(hashed) = self.hashed_ids() {
+                let events = crate::event_catalog()
+                    .filter(|event| !event.is_span())
+                    .map(|event| (event.name(), event.id()));
+                if let Some((id, first, second)) = crate::layer::find_collision(events, &hashed) {
+                    return Err(EtwError::EventIdCollision(
+                        id,
+                        first.to_string(),
+                        second.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The provider name is always a target, so that events can be sent to the layer by its name
+    pub(crate) fn build_target_filter<I, T, L>(&self, targets: I) -> Targets
+    where
+        I: IntoIterator<Item = (T, L)>,
+        T: Into<String>,
+        L: Into<LevelFilter>,
+    {
+        Targets::new()
+            .with_target(&*self.provider_name, LevelFilter::TRACE)
+            .with_targets(targets.into_iter().filter_map(|(target, level)| {
+                let target = target.into();
+                (!target.is_empty()).then(|| (target, level.into()))
+            }))
+    }
+
+    // The targets are kept in the layer's settings, rather than in a filter of their own,
+    // so that they can be replaced with a ReloadHandle
+    pub(crate) fn set_target_filter(&self, targets: Targets) {
+        self.settings.rcu(|current| LayerSettings {
+            targets: Some(targets.clone()),
+            ..LayerSettings::clone(current)
+        });
     }
 
     // Builds a layer without any enable checks, unless global_filter is enabled
@@ -221,15 +672,27 @@ impl<OutMode: OutputMode + 'static> LayerBuilder<OutMode> {
         S: Subscriber + for<'a> LookupSpan<'a>,
         crate::native::Provider<OutMode>: ProviderTraits + EventWriter<OutMode>,
     {
+        let default_keywords: Vec<u64> = std::iter::once(self.default_keyword)
+            .chain(self.target_keywords.keywords())
+            .collect();
+
+        self.settings.rcu(|current| LayerSettings {
+            default_keyword: self.default_keyword,
+            target_keywords: self.target_keywords.clone(),
+            ..LayerSettings::clone(current)
+        });
+
         EtwLayer::<S, OutMode> {
             layer: _EtwLayer {
                 provider: crate::native::Provider::<OutMode>::new(
                     &self.provider_name,
                     &self.provider_id,
                     &self.provider_group,
-                    self.default_keyword,
+                    &default_keywords,
                 ),
-                default_keyword: self.default_keyword,
+                settings: self.settings.clone(),
+                hashed_event_ids: self.hashed_ids(),
+                payload: Arc::new(self.payload.clone()),
                 _p: PhantomData,
             },
         }
@@ -287,6 +750,9 @@ impl<OutMode: OutputMode + 'static> LayerBuilder<OutMode> {
     /// Constructs the configured layer with a target [tracing_subscriber::filter] applied.
     /// This can be used to target specific events to specific layers, and in effect allow
     /// specific events to be logged only from specific ETW/user_event providers.
+    /// Use [LayerBuilder::build_with_targets] for more than one target, or for levels other
+    /// than [LevelFilter::TRACE], and [LayerBuilder::build_with_target_filter] for any other
+    /// [Targets] filter.
     ///
     /// ```
     /// # use tracing::event;
@@ -311,20 +777,95 @@ impl<OutMode: OutputMode + 'static> LayerBuilder<OutMode> {
     pub fn build_with_target<S>(
         self,
         target: &'static str,
-    ) -> Result<Filtered<EtwLayer<S, OutMode>, And<EtwFilter<S, OutMode>, Targets, S>, S>, EtwError>
+    ) -> Result<Filtered<EtwLayer<S, OutMode>, EtwFilter<S, OutMode>, S>, EtwError>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        crate::native::Provider<OutMode>: ProviderTraits + EventWriter<OutMode>,
+    {
+        self.build_with_targets([(target, LevelFilter::TRACE)])
+    }
+
+    /// Constructs the configured layer with a target [tracing_subscriber::filter] for
+    /// each of the given targets, at the given levels.
+    ///
+    /// As with [LayerBuilder::build_with_target], the provider name is also always
+    /// added as a target, at [LevelFilter::TRACE]. Events must also be enabled by an
+    /// ETW session or user_events tracepoint to be logged.
+    ///
+    /// ```
+    /// # use tracing::Level;
+    /// # use tracing_subscriber::prelude::*;
+    /// # let reg = tracing_subscriber::registry();
+    /// let built_layer = tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .build_with_targets([("myapp", Level::DEBUG), ("hyper", Level::WARN)]);
+    /// assert!(built_layer.is_ok());
+    /// # reg.with(built_layer.unwrap());
+    /// ```
+    ///
+    #[allow(clippy::type_complexity)]
+    #[cfg_attr(docsrs, doc(cfg(not(feature = "global_filter"))))]
+    #[cfg(any(not(feature = "global_filter"), docsrs))]
+    pub fn build_with_targets<S, I, T, L>(
+        self,
+        targets: I,
+    ) -> Result<Filtered<EtwLayer<S, OutMode>, EtwFilter<S, OutMode>, S>, EtwError>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        crate::native::Provider<OutMode>: ProviderTraits + EventWriter<OutMode>,
+        I: IntoIterator<Item = (T, L)>,
+        T: Into<String>,
+        L: Into<LevelFilter>,
+    {
+        let targets = self.build_target_filter(targets);
+
+        self.build_with_target_filter(targets)
+    }
+
+    /// Constructs the configured layer with the given [Targets] filter applied as is.
+    /// Unlike [LayerBuilder::build_with_target], the provider name is not added as a target,
+    /// so the filter can also set a default level or disable targets.
+    ///
+    /// The filter is checked after the layer's own check that the event is enabled by an
+    /// ETW session or user_events tracepoint. The targets given to this method and to
+    /// [LayerBuilder::build_with_targets] can be replaced after the layer is built with
+    /// [ReloadHandle::set_target_filter].
+    ///
+    /// ```
+    /// # use tracing::Level;
+    /// # use tracing_subscriber::{filter::Targets, prelude::*};
+    /// # let reg = tracing_subscriber::registry();
+    /// let targets = Targets::new()
+    ///     .with_default(Level::INFO)
+    ///     .with_target("myapp::db", Level::TRACE)
+    ///     .with_target("hyper", tracing::level_filters::LevelFilter::OFF);
+    ///
+    /// let built_layer = tracing_etw::LayerBuilder::new("SampleProviderName")
+    ///     .build_with_target_filter(targets);
+    /// assert!(built_layer.is_ok());
+    /// # reg.with(built_layer.unwrap());
+    /// ```
+    ///
+    #[allow(clippy::type_complexity)]
+    #[cfg_attr(docsrs, doc(cfg(not(feature = "global_filter"))))]
+    #[cfg(any(not(feature = "global_filter"), docsrs))]
+    pub fn build_with_target_filter<S>(
+        self,
+        targets: Targets,
+    ) -> Result<Filtered<EtwLayer<S, OutMode>, EtwFilter<S, OutMode>, S>, EtwError>
     where
         S: Subscriber + for<'a> LookupSpan<'a>,
         crate::native::Provider<OutMode>: ProviderTraits + EventWriter<OutMode>,
     {
         self.validate_config()?;
 
+        self.set_target_filter(targets);
+
         let layer = self.build_layer();
 
+        // The targets are kept in the settings, and checked by the layer's own filter
         let filter = self.build_filter(layer.layer.clone());
 
-        let targets = self.build_target_filter(target);
-
-        Ok(layer.with_filter(filter.and(targets)))
+        Ok(layer.with_filter(filter))
     }
 
     // Private. For integration tests only. Skips adding enablement checks. Serves
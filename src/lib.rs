@@ -54,6 +54,14 @@
 //! event!(Level::INFO, fieldB = b'x', fieldA = 7, "Event Message!");
 //! ```
 //!
+//! The provider name, keywords, target filters, and output mode can also be read from an
+//! environment variable with [LayerBuilder::from_env] (e.g.
+//! `TRACING_ETW=provider=MyProv;keyword=0x10;targets=myapp=debug,hyper=warn`), so that
+//! they can be changed without a rebuild. With the `serde` feature, a `LayerConfig`
+//! deserialized from a configuration file can be used instead, with `LayerBuilder::from_config`.
+//! The default keyword, the keywords of targets, and a target filter can also be changed
+//! after the layer is built, with a [ReloadHandle] from [LayerBuilder::reload_handle].
+//!
 //! ## etw_event macro
 //!
 //! **Despite the name, this macro works for both ETW and user_events.**
@@ -65,10 +73,78 @@
 //! It is highly recommended that every event have a non-zero keyword;
 //! the [LayerBuilder::with_default_keyword] function can set the default keyword assigned
 //! to every event logged through the `tracing` macros (e.g. `event!`).
+//! Keywords can be defined with names using [etw_keywords!], which are then shown
+//! in the [event_catalog] and in diagnostics.
+//!
+//! The name can optionally be followed by `opcode:` (see [Opcode]), `version:`,
+//! `channel:`, and `id:` arguments, which set the corresponding fields in the event header.
+//! The channel is only used by ETW.
+//! Events without an `id:` have ID 0, unless the layer is built with
+//! [LayerBuilder::with_hashed_event_ids], which gives each event name a stable ID instead.
+//! A `parent:` argument can also be given to set the parent span of the event, as with
+//! `event!`, for events logged outside of the scope of the span they belong to.
+//!
+//! ```
+//! use tracing::Level;
+//! use tracing_etw::{etw_event, Opcode};
+//!
+//! etw_event!(name: "Request.Send", opcode: Opcode::Send, version: 2, Level::INFO, 0x10, "Sending request");
+//! etw_event!(name: "Request.Done", id: 12, Level::INFO, 0x10, "Request done");
+//! ```
 //!
 //! This extra information is stored as static metadata in the final
 //! compiled binary, and relies on linker support to work properly.
 //! It has been tested with Microsoft's, GCC's, and LLVM's linker.
+//! On Linux, the metadata of shared objects that are loaded later (e.g. plugins
+//! loaded with `dlopen`) is found when their callsites are first registered.
+//! Such shared objects must not be unloaded afterwards.
+//! If the linker section is not available (e.g. it is removed by the linker),
+//! the `runtime_metadata` feature registers the metadata of each callsite the first time
+//! it runs instead. The [event_catalog] then only lists callsites that have run, and on
+//! Linux, the tracepoints for a keyword are only created once a callsite with that
+//! keyword has run.
+//!
+//! ## etw_span macro
+//!
+//! The `etw_span!` macro does the same for spans: it is based on `span!`, and the
+//! keyword, tag, and name it is given are used for the events logged when the span
+//! is entered and exited. Spans created with the `tracing` macros use the default keyword.
+//! As with `etw_event!`, a `parent:` argument after the name sets the parent of the span.
+//!
+//! ```
+//! use tracing::Level;
+//! use tracing_etw::etw_span;
+//!
+//! let span = etw_span!(name: "Db.Query", Level::INFO, 0x10, table = "users");
+//! let _guard = span.enter();
+//! ```
+//!
+//! With the `attributes` feature enabled, the `#[etw_instrument]` attribute can be
+//! used in place of `#[tracing::instrument]` to create such a span for every call to
+//! a function, e.g. `#[etw_instrument(keyword = 0x10, tag = 3, name = "Db.Query")]`.
+//!
+//! Every `etw_event!` and `etw_span!` callsite in the binary can be listed with [event_catalog],
+//! e.g. to print the events a program may log or to generate collection profiles.
+//! The `tracing-etw-catalog` tool prints the same catalog as JSON from a compiled ELF binary,
+//! without running it.
+//! [validate_event_metadata] checks every `etw_event!` and `etw_span!` callsite in the binary
+//! for problems such as the same event name being used with different keywords, and
+//! [LayerBuilder::with_event_metadata_validation] fails to build the layer if any are found.
+//!
+//! ## Field formats
+//!
+//! Values can be wrapped in the types from the [formats] module (e.g. [formats::Hex] or
+//! [formats::Ipv4]) to log them with a format hint that trace decoders use to display them,
+//! rather than as a plain integer or string.
+//!
+//! ## Redaction
+//!
+//! Fields that may contain personal or sensitive data can be dropped, hashed, or masked
+//! before they are written, by field name ([LayerBuilder::with_field_redaction]) or for
+//! all fields from a target ([LayerBuilder::with_target_redaction]). See [Redaction].
+//!
+//! Layers that log Common Schema events can also rename fields, drop them, or move them
+//! into PartB with [LayerBuilder::with_field_mapping].
 //!
 //! ## Performance Details
 //!
@@ -118,42 +194,34 @@
 //! first event has been logged.
 //!
 //! - Each `tracing-etw::Layer` that is added will heap allocate some internal state
-//! when `build` is called.
-//!
-//! - Logging events with the [Debug][std::fmt::Debug] format specifier (`:?`) will
-//! necessitate a heap allocation to format the value into a string.
-//!
-//! <div class="warning">
+//!   when `build` is called.
 //!
-//! All event messages (the format strings to the event) are, for reasons
-//! outside of the control of this crate, formatted as debug formats rather than as
-//! a string. This effectively means every single event is forced to perform at least
-//! one heap allocation. In benchmarking, this does not seem to be a major performance issue.
-//!
-//! </div>
-//!
-//! - Logging strings copies them to the heap first. This is a side-effect of how
-//! `tracing` presents the strings to each layer; the lifetime of the string is
-//! too short for what this crate currently needs, but it may be possible to improve
-//! this in the future.
+//! - Event values logged with the [Debug][std::fmt::Debug] format specifier (`:?`),
+//!   including every event message, are formatted into a thread-local scratch buffer
+//!   that is reused for every event logged on that thread. Like the event builder scratch
+//!   space described below, this buffer grows to fit the largest value formatted so far
+//!   and does not shrink, so logging an event only allocates when a value is larger than
+//!   anything previously formatted on the thread.
 //!
 //! - Logging a span allocates a copy of the span's fields on the heap. This is needed
-//! so the values can be updated during execution and the final payload values logged
-//! when the span ends. This allocation is freed when the span ends.
+//!   so the values can be updated during execution and the final payload values logged
+//!   when the span ends. String and [Debug][std::fmt::Debug] span values are copied to
+//!   the heap for the same reason, up to the limit set by [LayerBuilder::with_max_span_value_size].
+//!   These allocations are freed when the span ends.
 //!
 //! - The first time an event is logged (the event is enabled at the platform layer and
-//! the logging code is run), this crate will scan the binary for any metadata left
-//! by the `etw_event!` macro. This information will be cached in a single heap
-//! allocation for later use by other logging calls. This cached memory is never freed
-//! until the process exits; if this crate is used in a dynamic library that unloads
-//! before the process exits, the memory will be leaked.
+//!   the logging code is run), this crate will scan the binary for any metadata left
+//!   by the `etw_event!` macro. This information will be cached in a single heap
+//!   allocation for later use by other logging calls. This cached memory is never freed
+//!   until the process exits; if this crate is used in a dynamic library that unloads
+//!   before the process exits, the memory will be leaked.
 //!
 //! - A thread-local event builder is allocated for each thread that logs an event.
-//! This allows for complete thread safety when logging events. This allocation
-//! will stay alive until the thread ends. Additionally, the builder itself will allocate
-//! scratch space for constructing the event. This scratch space will grow to fit the
-//! very largest event that has been logged so far, but will not shrink. Generally,
-//! this should not be much more than a few kilobytes per-thread.
+//!   This allows for complete thread safety when logging events. This allocation
+//!   will stay alive until the thread ends. Additionally, the builder itself will allocate
+//!   scratch space for constructing the event. This scratch space will grow to fit the
+//!   very largest event that has been logged so far, but will not shrink. Generally,
+//!   this should not be much more than a few kilobytes per-thread.
 //!
 //! ### Miscellaneous
 //!
@@ -183,32 +251,87 @@
 // the `docsrs` configuration attribute is defined
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+mod catalog;
+#[cfg(feature = "serde")]
+mod config;
+mod directives;
+mod dynamic_layer_builder;
+mod field_mapping;
 mod layer_builder;
+mod opcode;
+mod redaction;
 // Module that abstracts the native ETW and Linux user_events APIs, depending on the target platform.
 // Consumers of the crate should not need to use this module directly.
 #[doc(hidden)]
 pub mod native;
 mod statics;
+mod validation;
 mod values;
 // Module holding internal details that need to be public but should not be directly used by consumers of the crate.
 #[doc(hidden)]
 pub mod _details;
 pub mod error;
+pub mod formats;
 
 // OpenTelemetry integration module - only available with the "opentelemetry" feature
 #[cfg(feature = "opentelemetry")]
 #[cfg_attr(docsrs, doc(cfg(feature = "opentelemetry")))]
 pub(crate) mod otel;
 
+pub use catalog::{event_catalog, CatalogEntry, EventCatalog};
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+pub use config::{LayerConfig, LayerOutput};
+pub use dynamic_layer_builder::DynamicLayerBuilder;
+pub use field_mapping::FieldMapping;
+pub use layer::ReloadHandle;
 pub use layer_builder::LayerBuilder;
+pub use opcode::Opcode;
+pub use redaction::Redaction;
+#[cfg(feature = "attributes")]
+#[cfg_attr(docsrs, doc(cfg(feature = "attributes")))]
+pub use tracing_etw_macros::etw_instrument;
+pub use validation::{validate_event_metadata, MetadataIssue};
 
 mod layer;
 
 #[macro_export]
 macro_rules! etw_event {
-    (target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, $tags:expr, { $($fields:tt)* } )=> ({
+    // Optional arguments following the name are collected into @[...] before matching the other arms
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, opcode: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? opcode: $value,] target: $target, name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, version: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? version: $value,] target: $target, name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, channel: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? channel: $value,] target: $target, name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, id: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? id: $value,] target: $target, name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, parent: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? parent: $value,] target: $target, name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? name: $name:expr, opcode: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? opcode: $value,] target: module_path!(), name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? name: $name:expr, version: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? version: $value,] target: module_path!(), name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? name: $name:expr, channel: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? channel: $value,] target: module_path!(), name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? name: $name:expr, id: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? id: $value,] target: module_path!(), name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? name: $name:expr, parent: $value:expr, $($rest:tt)+ ) => (
+        $crate::etw_event!(@[$($($opts)*)? parent: $value,] target: module_path!(), name: $name, $($rest)+)
+    );
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, $tags:expr, { $($fields:tt)* } )=> ({
         use tracing::Callsite;
-        use paste::paste;
+
+        const FIELD_NAMES: &[&str] = tracing::fieldset!( $($fields)* );
 
         static CALLSITE: tracing::callsite::DefaultCallsite =
             tracing::callsite::DefaultCallsite::new(
@@ -222,7 +345,7 @@ macro_rules! etw_event {
                         Some(file!()),
                         Some(line!()),
                         Some(module_path!()),
-                        tracing::field::FieldSet::new(tracing::fieldset!( $($fields)* ), tracing_core::identify_callsite!(&CALLSITE)),
+                        tracing::field::FieldSet::new(FIELD_NAMES, tracing_core::identify_callsite!(&CALLSITE)),
                         tracing::metadata::Kind::EVENT,
                 );
                 &META
@@ -231,29 +354,32 @@ macro_rules! etw_event {
 
         #[used]
         static ETW_META: $crate::_details::EventMetadata = $crate::_details::EventMetadata{
+            header: $crate::_details::RecordHeader::CURRENT,
             kw: $kw,
-            // TODO: Hash the callsite identity at compile time, or get Identifier to implement Ord
             identity: tracing_core::identify_callsite!(&CALLSITE),
-            event_tag: $tags as u32
+            event_tag: $tags as u32,
+            options: $crate::_details::EventOptions {
+                opcode: $crate::Opcode::as_int($crate::__etw_option!(opcode = $crate::Opcode::Info; $($($opts)*)?)),
+                version: $crate::__etw_option!(version = 0; $($($opts)*)?),
+                channel: $crate::__etw_option!(channel = $crate::_details::EventOptions::DEFAULT.channel; $($($opts)*)?),
+                id: $crate::__etw_option!(id = 0; $($($opts)*)?),
+            },
+            description: $crate::_details::EventDescription {
+                name: $crate::_details::StaticStr::new($name),
+                target: $crate::_details::StaticStr::new($target),
+                file: $crate::_details::StaticStr::new(file!()),
+                fields: {
+                    static FIELDS: [$crate::_details::StaticStr; FIELD_NAMES.len()] = $crate::_details::StaticStr::array(FIELD_NAMES);
+                    FIELDS.as_ptr()
+                },
+                field_count: FIELD_NAMES.len(),
+                line: line!(),
+                level: $crate::_details::level_number($lvl),
+                is_span: 0,
+            },
         };
 
-        // These two statics need to be mut to avoid compiler errors about *const EventMetadata not being Sync.
-
-        paste! {
-            #[cfg(target_os = "linux")]
-            #[unsafe(link_section = "_etw_kw")]
-            #[allow(non_upper_case_globals)]
-            #[used]
-            static mut [<ETW_META_PTR $name>]: *const $crate::_details::EventMetadata = &ETW_META;
-        }
-
-        paste! {
-            #[cfg(target_os = "windows")]
-            #[unsafe(link_section = ".rdata$zRSETW5")]
-            #[allow(non_upper_case_globals)]
-            #[used]
-            static mut [<ETW_META_PTR $name>]: *const $crate::_details::EventMetadata = &ETW_META;
-        }
+        $crate::__etw_register_metadata!(ETW_META);
 
         let enabled = tracing::level_enabled!($lvl) && {
             let interest = CALLSITE.interest();
@@ -262,27 +388,25 @@ macro_rules! etw_event {
         if enabled {
             (|value_set: tracing::field::ValueSet| {
                 let meta = CALLSITE.metadata();
-                // event with contextual parent
-                tracing::Event::dispatch(
-                    meta,
-                    &value_set
-                );
+                // event with explicit or contextual parent
+                $crate::__etw_dispatch!(meta, &value_set; $($($opts)*)?);
                 tracing::__tracing_log!(
                     $lvl,
                     CALLSITE,
                     &value_set
                 );
-            })(tracing::valueset!(CALLSITE.metadata().fields(), $($fields)*));
+            })(tracing::valueset_all!(CALLSITE.metadata().fields(), $($fields)*));
         } else {
             tracing::__tracing_log!(
                 $lvl,
                 CALLSITE,
-                &tracing::valueset!(CALLSITE.metadata().fields(), $($fields)*)
+                &tracing::valueset_all!(CALLSITE.metadata().fields(), $($fields)*)
             );
         }
     });
-    (target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, { $($fields:tt)* }, $($arg:tt)+ ) => (
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, { $($fields:tt)* }, $($arg:tt)+ ) => (
         $crate::etw_event!(
+            $(@[$($opts)*])?
             target: $target,
             name: $name,
             $lvl,
@@ -291,24 +415,15 @@ macro_rules! etw_event {
             { message = format_args!($($arg)+), $($fields)* }
         )
     );
-    (target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, $($k:ident).+ = $($fields:tt)* ) => (
-        $crate::etw_event!(target: $target, name: $name, $lvl, $kw, 0, { $($k).+ = $($fields)* })
-    );
-    (target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, $($arg:tt)+ ) => (
-        $crate::etw_event!(target: $target, name: $name, $lvl, $kw, 0, { $($arg)+ })
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, $($k:ident).+ = $($fields:tt)* ) => (
+        $crate::etw_event!($(@[$($opts)*])? target: $target, name: $name, $lvl, $kw, 0, { $($k).+ = $($fields)* })
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, { $($fields:tt)* }, $($arg:tt)+ ) => (
-        $crate::etw_event!(
-            target: module_path!(),
-            name: $name,
-            $lvl,
-            $kw,
-            0,
-            { message = format_args!($($arg)+), $($fields)* }
-        )
+    ($(@[$($opts:tt)*])? target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, $($arg:tt)+ ) => (
+        $crate::etw_event!($(@[$($opts)*])? target: $target, name: $name, $lvl, $kw, 0, { $($arg)+ })
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, { $($fields:tt)* }, $($arg:tt)+ ) => (
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, { $($fields:tt)* }, $($arg:tt)+ ) => (
         $crate::etw_event!(
+            $(@[$($opts)*])?
             target: module_path!(),
             name: $name,
             $lvl,
@@ -317,8 +432,9 @@ macro_rules! etw_event {
             { message = format_args!($($arg)+), $($fields)* }
         )
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, $($k:ident).+ = $($field:tt)*) => (
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, $($k:ident).+ = $($field:tt)*) => (
         $crate::etw_event!(
+            $(@[$($opts)*])?
             target: module_path!(),
             name: $name,
             $lvl,
@@ -327,8 +443,9 @@ macro_rules! etw_event {
             { $($k).+ = $($field)*}
         )
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, $($k:ident).+, $($field:tt)*) => (
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, $($k:ident).+, $($field:tt)*) => (
         $crate::etw_event!(
+            $(@[$($opts)*])?
             target: module_path!(),
             name: $name,
             $lvl,
@@ -337,8 +454,9 @@ macro_rules! etw_event {
             { $($k).+, $($field)*}
         )
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, ?$($k:ident).+, $($field:tt)*) => (
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, ?$($k:ident).+, $($field:tt)*) => (
         $crate::etw_event!(
+            $(@[$($opts)*])?
             target: module_path!(),
             name: $name,
             $lvl,
@@ -347,8 +465,9 @@ macro_rules! etw_event {
             { ?$($k).+, $($field)*}
         )
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, %$($k:ident).+, $($field:tt)*) => (
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, %$($k:ident).+, $($field:tt)*) => (
         $crate::etw_event!(
+            $(@[$($opts)*])?
             target: module_path!(),
             name: $name,
             $lvl,
@@ -357,18 +476,19 @@ macro_rules! etw_event {
             { %$($k).+, $($field)*}
         )
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, ?$($k:ident).+) => (
-        $crate::etw_event!(name: $name, $lvl, $kw, 0, ?$($k).+,)
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, ?$($k:ident).+) => (
+        $crate::etw_event!($(@[$($opts)*])? name: $name, $lvl, $kw, ?$($k).+,)
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, %$($k:ident).+) => (
-        $crate::etw_event!(name: $name, $lvl, $kw, 0, %$($k).+,)
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, %$($k:ident).+) => (
+        $crate::etw_event!($(@[$($opts)*])? name: $name, $lvl, $kw, %$($k).+,)
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, $($k:ident).+) => (
-        $crate::etw_event!(name: $name, $lvl, $kw, 0, $($k).+,)
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, $($k:ident).+) => (
+        $crate::etw_event!($(@[$($opts)*])? name: $name, $lvl, $kw, $($k).+,)
     );
     // Handle bare message string: etw_event!(name: "Name", Level::INFO, 1, "message")
-    (name: $name:expr, $lvl:expr, $kw:expr, $msg:literal) => (
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, $msg:literal) => (
         $crate::etw_event!(
+            $(@[$($opts)*])?
             target: module_path!(),
             name: $name,
             $lvl,
@@ -377,7 +497,283 @@ macro_rules! etw_event {
             { message = $msg }
         )
     );
-    (name: $name:expr, $lvl:expr, $kw:expr, $($arg:tt)+ ) => (
-        $crate::etw_event!(target: module_path!(), name: $name, $lvl, $kw, 0, { $($arg)+ })
+    ($(@[$($opts:tt)*])? name: $name:expr, $lvl:expr, $kw:expr, $($arg:tt)+ ) => (
+        $crate::etw_event!($(@[$($opts)*])? target: module_path!(), name: $name, $lvl, $kw, 0, { $($arg)+ })
+    );
+}
+
+// Finds the value of an optional etw_event! argument in the list collected by the macro
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __etw_option {
+    (opcode = $default:expr; opcode: $value:expr, $($rest:tt)*) => ( $value );
+    (version = $default:expr; version: $value:expr, $($rest:tt)*) => ( $value );
+    (channel = $default:expr; channel: $value:expr, $($rest:tt)*) => ( $value );
+    (id = $default:expr; id: $value:expr, $($rest:tt)*) => ( $value );
+    ($key:ident = $default:expr; $other:ident: $value:expr, $($rest:tt)*) => (
+        $crate::__etw_option!($key = $default; $($rest)*)
+    );
+    ($key:ident = $default:expr;) => ( $default );
+}
+
+// Makes the EventMetadata of a callsite available to the layer, by placing a pointer to it in
+// a linker section that is read when the layer is first used.
+#[cfg(not(feature = "runtime_metadata"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __etw_register_metadata {
+    ($meta:ident) => {
+        // These two statics need to be mut to avoid compiler errors about *const EventMetadata not being Sync.
+        // Each invocation is its own block, so the names cannot collide, and the event name does not
+        // need to be a valid identifier.
+
+        #[cfg(target_os = "linux")]
+        #[unsafe(link_section = "_etw_kw")]
+        #[used]
+        static mut ETW_META_PTR: *const $crate::_details::EventMetadata = &$meta;
+
+        #[cfg(target_os = "windows")]
+        #[unsafe(link_section = ".rdata$zRSETW5")]
+        #[used]
+        static mut ETW_META_PTR: *const $crate::_details::EventMetadata = &$meta;
+    };
+}
+
+// Makes the KeywordDefinition of a keyword defined with etw_keywords! available, in the same linker
+// section as the EventMetadata of the callsites.
+#[cfg(not(feature = "runtime_metadata"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __etw_register_keyword {
+    ($def:ident) => {
+        #[cfg(target_os = "linux")]
+        #[unsafe(link_section = "_etw_kw")]
+        #[used]
+        static mut ETW_KEYWORD_PTR: *const $crate::_details::KeywordDefinition = &$def;
+
+        #[cfg(target_os = "windows")]
+        #[unsafe(link_section = ".rdata$zRSETW5")]
+        #[used]
+        static mut ETW_KEYWORD_PTR: *const $crate::_details::KeywordDefinition = &$def;
+    };
+}
+
+// With the runtime_metadata feature, there is no code that runs to register the keyword,
+// so its name is not recorded.
+#[cfg(feature = "runtime_metadata")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __etw_register_keyword {
+    ($def:ident) => {};
+}
+
+// With the runtime_metadata feature, the EventMetadata is registered the first time the callsite
+// runs instead. This must happen before the callsite is registered with tracing.
+#[cfg(feature = "runtime_metadata")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __etw_register_metadata {
+    ($meta:ident) => {
+        static ETW_META_REGISTERED: std::sync::Once = std::sync::Once::new();
+        ETW_META_REGISTERED.call_once(|| $crate::_details::register_event_metadata(&$meta));
+    };
+}
+
+// Dispatches an etw_event! event, with an explicit parent if one was given
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __etw_dispatch {
+    ($meta:expr, $value_set:expr; parent: $parent:expr, $($rest:tt)*) => (
+        tracing::Event::child_of($parent, $meta, $value_set)
+    );
+    ($meta:expr, $value_set:expr; $other:ident: $value:expr, $($rest:tt)*) => (
+        $crate::__etw_dispatch!($meta, $value_set; $($rest)*)
+    );
+    ($meta:expr, $value_set:expr;) => (
+        tracing::Event::dispatch($meta, $value_set)
+    );
+}
+
+/// Defines named keywords, as `u64` constants that can be used anywhere a keyword is expected,
+/// such as in [etw_event!], [etw_span!], and [LayerBuilder::with_default_keyword].
+///
+/// The names of the keywords are recorded with the metadata of the `etw_event!` and `etw_span!`
+/// callsites, so that the [event_catalog] ([CatalogEntry::keyword_names]), the diagnostics from
+/// [validate_event_metadata], and the manifests generated by the `tracing-etw-catalog` tool can
+/// show `Network` instead of `0x1`. A keyword with several bits set is only named for events that
+/// set all of them.
+///
+/// With the `runtime_metadata` feature, the names are not recorded.
+///
+/// ```
+/// use tracing::Level;
+/// use tracing_etw::{etw_event, etw_keywords};
+///
+/// etw_keywords! {
+///     /// Network requests and responses
+///     pub Network = 0x1,
+///     pub Storage = 0x2,
+/// }
+///
+/// etw_event!(name: "Upload", Level::INFO, Network | Storage, "Uploading file");
+/// ```
+#[macro_export]
+macro_rules! etw_keywords {
+    ($($(#[$attr:meta])* $vis:vis $name:ident = $value:expr),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            #[allow(non_upper_case_globals)]
+            $vis const $name: u64 = $value;
+
+            const _: () = {
+                #[used]
+                static ETW_KEYWORD: $crate::_details::KeywordDefinition = $crate::_details::KeywordDefinition {
+                    header: $crate::_details::RecordHeader::KEYWORD,
+                    value: $name,
+                    name: $crate::_details::StaticStr::new(stringify!($name)),
+                };
+
+                $crate::__etw_register_keyword!(ETW_KEYWORD);
+            };
+        )*
+    };
+}
+
+/// Creates a span with a keyword, a tag, and a name, like [tracing::span!].
+///
+/// The keyword, tag, and name are used for the events that are logged when the span is
+/// entered and exited, and are listed in the [event_catalog]. The target defaults to the
+/// module path, and the tag to 0. Fields are given as with `span!`, either directly after
+/// the keyword, or in braces after the tag, optionally followed by a message.
+///
+/// A `parent:` argument after the name sets the parent of the span, instead of the
+/// current span, as with `span!`.
+///
+/// ```
+/// use tracing::Level;
+/// use tracing_etw::etw_span;
+///
+/// let request = etw_span!(name: "Request", Level::INFO, 0x10, id = 42);
+/// let query = etw_span!(
+///     target: "db",
+///     name: "Db.Query",
+///     parent: &request,
+///     Level::INFO,
+///     0x20,
+///     3,
+///     { table = "users" },
+///     "querying {}",
+///     "users"
+/// );
+/// let _guard = query.enter();
+/// ```
+#[macro_export]
+macro_rules! etw_span {
+    // The parent is collected into @[...] before matching the other arms
+    (target: $target:expr, name: $name:expr, parent: $parent:expr, $($rest:tt)+ ) => (
+        $crate::etw_span!(@[parent: $parent] target: $target, name: $name, $($rest)+)
+    );
+    (name: $name:expr, parent: $parent:expr, $($rest:tt)+ ) => (
+        $crate::etw_span!(@[parent: $parent] target: module_path!(), name: $name, $($rest)+)
+    );
+    ($(@[parent: $parent:expr])? target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, $tags:expr, { $($fields:tt)* } )=> ({
+        use tracing::Callsite;
+
+        const FIELD_NAMES: &[&str] = tracing::fieldset!( $($fields)* );
+
+        static CALLSITE: tracing::callsite::DefaultCallsite =
+            tracing::callsite::DefaultCallsite::new(
+            {
+                const SPAN_NAME: &'static str = $name;
+                static META: tracing::metadata::Metadata =
+                    tracing::metadata::Metadata::new(
+                        SPAN_NAME,
+                        $target,
+                        $lvl,
+                        Some(file!()),
+                        Some(line!()),
+                        Some(module_path!()),
+                        tracing::field::FieldSet::new(FIELD_NAMES, tracing_core::identify_callsite!(&CALLSITE)),
+                        tracing::metadata::Kind::SPAN,
+                );
+                &META
+            }
+        );
+
+        #[used]
+        static ETW_META: $crate::_details::EventMetadata = $crate::_details::EventMetadata{
+            header: $crate::_details::RecordHeader::CURRENT,
+            kw: $kw,
+            identity: tracing_core::identify_callsite!(&CALLSITE),
+            event_tag: $tags as u32,
+            options: $crate::_details::EventOptions::DEFAULT,
+            description: $crate::_details::EventDescription {
+                name: $crate::_details::StaticStr::new($name),
+                target: $crate::_details::StaticStr::new($target),
+                file: $crate::_details::StaticStr::new(file!()),
+                fields: {
+                    static FIELDS: [$crate::_details::StaticStr; FIELD_NAMES.len()] = $crate::_details::StaticStr::array(FIELD_NAMES);
+                    FIELDS.as_ptr()
+                },
+                field_count: FIELD_NAMES.len(),
+                line: line!(),
+                level: $crate::_details::level_number($lvl),
+                is_span: 1,
+            },
+        };
+
+        $crate::__etw_register_metadata!(ETW_META);
+
+        let enabled = tracing::level_enabled!($lvl) && {
+            let interest = CALLSITE.interest();
+            !interest.is_never() && tracing::__macro_support::__is_enabled(CALLSITE.metadata(), interest)
+        };
+        if enabled {
+            let meta = CALLSITE.metadata();
+            // span with explicit or contextual parent
+            $crate::__etw_new_span!(
+                meta,
+                &tracing::valueset_all!(meta.fields(), $($fields)*);
+                $($parent)?
+            )
+        } else {
+            let span = tracing::__macro_support::__disabled_span(CALLSITE.metadata());
+            tracing::if_log_enabled! { $lvl, {
+                span.record_all(&tracing::valueset_all!(CALLSITE.metadata().fields(), $($fields)*));
+            }};
+            span
+        }
+    });
+    ($(@[parent: $parent:expr])? target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, $tags:expr, { $($fields:tt)* }, $($arg:tt)+ ) => (
+        $crate::etw_span!(
+            $(@[parent: $parent])?
+            target: $target,
+            name: $name,
+            $lvl,
+            $kw,
+            $tags,
+            { message = format_args!($($arg)+), $($fields)* }
+        )
+    );
+    ($(@[parent: $parent:expr])? target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr, { $($fields:tt)* } $(, $($arg:tt)+)? ) => (
+        $crate::etw_span!($(@[parent: $parent])? target: $target, name: $name, $lvl, $kw, 0, { $($fields)* } $(, $($arg)+)?)
+    );
+    ($(@[parent: $parent:expr])? target: $target:expr, name: $name:expr, $lvl:expr, $kw:expr $(, $($fields:tt)*)? ) => (
+        $crate::etw_span!($(@[parent: $parent])? target: $target, name: $name, $lvl, $kw, 0, { $($($fields)*)? })
+    );
+    (name: $name:expr, $lvl:expr, $kw:expr $(, $($fields:tt)*)? ) => (
+        $crate::etw_span!(target: module_path!(), name: $name, $lvl, $kw $(, $($fields)*)?)
+    );
+}
+
+// Creates an etw_span! span, with an explicit parent if one was given
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __etw_new_span {
+    ($meta:expr, $value_set:expr; $parent:expr) => (
+        tracing::Span::child_of($parent, $meta, $value_set)
+    );
+    ($meta:expr, $value_set:expr;) => (
+        tracing::Span::new($meta, $value_set)
     );
 }
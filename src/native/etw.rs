@@ -1,8 +1,11 @@
 use crate::statics::GLOBAL_ACTIVITY_SEED;
 use crate::{
     error::EtwError,
+    field_mapping::{CommonSchemaPart, FieldMappings},
+    formats::FormattedValue,
     native::{CommonSchemaOutput, NormalOutput, OutputMode},
-    values::{event_values::*, *},
+    redaction::RedactionScope,
+    values::{event_values::*, span_values::SpanFieldWriter, *},
 };
 use chrono::{Datelike, Timelike};
 use std::marker::PhantomData;
@@ -13,16 +16,22 @@ use tracing_subscriber::registry::{LookupSpan, SpanRef};
 
 // Items within this .rdata section will be sorted alphabetically, thus the start is named with "0", the end "9", and each metadata "5".
 // If these statics aren't mut then everything will silently fail to work.
+#[cfg(not(feature = "runtime_metadata"))]
 #[allow(non_upper_case_globals)]
 #[unsafe(link_section = ".rdata$zRSETW0")]
 pub(crate) static mut _start__etw_kw: usize = 0;
+#[cfg(not(feature = "runtime_metadata"))]
 #[allow(non_upper_case_globals)]
 #[unsafe(link_section = ".rdata$zRSETW9")]
 pub(crate) static mut _stop__etw_kw: usize = 0;
 
 pub(crate) type ProviderGroupType = crate::native::native_guid;
 
-thread_local! {static EBW: std::cell::RefCell<EventBuilder>  = RefCell::new(EventBuilder::new());}
+thread_local! {
+    static EBW: std::cell::RefCell<EventBuilder> = RefCell::new(EventBuilder::new());
+    // Debug-formatted values (including every event message) are written here before being added to the event
+    static FMT_BUF: std::cell::RefCell<String> = const { RefCell::new(String::new()) };
+}
 
 struct Win32SystemTime {
     st: [u16; 8],
@@ -76,6 +85,30 @@ impl AddFieldAndValue for &'_ mut tracelogging_dynamic::EventBuilder {
                 // Or add_str16 with a 1-char (BMP) or 2-char (surrogate-pair) string.
                 self.add_u16(fv.field_name, *c as u16, OutType::String, 0);
             }
+            ValueTypes::v_formatted(f) => match *f {
+                FormattedValue::Hex(u) => {
+                    self.add_u64(fv.field_name, u, OutType::Hex, 0);
+                }
+                FormattedValue::Ipv4(addr) => {
+                    // TraceLogging expects the address in network byte order
+                    self.add_u32(fv.field_name, u32::from_ne_bytes(addr), OutType::IPv4, 0);
+                }
+                FormattedValue::Ipv6(addr) => {
+                    self.add_binary(fv.field_name, addr, OutType::IPv6, 0);
+                }
+                FormattedValue::Guid(u) => {
+                    self.add_guid(fv.field_name, &Guid::from_u128(&u), OutType::Default, 0);
+                }
+                FormattedValue::Errno(i) => {
+                    self.add_i32(fv.field_name, i, OutType::Default, 0);
+                }
+                FormattedValue::Pid(u) => {
+                    self.add_u32(fv.field_name, u, OutType::Pid, 0);
+                }
+                FormattedValue::Hresult(i) => {
+                    self.add_i32(fv.field_name, i, OutType::HResult, 0);
+                }
+            },
         }
     }
 }
@@ -93,6 +126,20 @@ fn callback_fn(
     tracing::callsite::rebuild_interest_cache();
 }
 
+// Logs the original length of any values that were truncated to fit in the event, as a
+// `_truncated` struct with one field per truncated value.
+fn add_truncation_marker(eb: &mut EventBuilder, truncated: &[(&'static str, u64)]) {
+    if truncated.is_empty() {
+        return;
+    }
+
+    let count = truncated.len().min(crate::values::MAX_TRUNCATED_FIELDS);
+    eb.add_struct("_truncated", count as u8, 0);
+    for (field_name, original_len) in &truncated[..count] {
+        eb.add_u64(field_name, *original_len, OutType::Default, 0);
+    }
+}
+
 #[doc(hidden)]
 pub struct Provider<Mode: OutputMode> {
     provider: tracelogging_dynamic::Provider,
@@ -126,7 +173,7 @@ impl<Mode: OutputMode> crate::native::ProviderTraits for Provider<Mode> {
         provider_name: &str,
         provider_id: &G,
         provider_group: &Option<ProviderGroupType>,
-        _default_keyword: u64,
+        _default_keywords: &[u64],
     ) -> Pin<Arc<Self>>
     where
         for<'a> &'a G: Into<crate::native::GuidWrapper>,
@@ -185,6 +232,7 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        payload: &PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -203,15 +251,9 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
                 0,
             );
 
-            for f in fields {
-                <&mut EventBuilder as AddFieldAndValue>::add_field_value(
-                    &mut eb.deref_mut(),
-                    &FieldAndValue {
-                        field_name: f.field,
-                        value: &f.value,
-                    },
-                );
-            }
+            let mut writer = SpanFieldWriter::new(payload.field_budget(span_name));
+            writer.add_fields(&mut eb.deref_mut(), fields);
+            add_truncation_marker(&mut eb, &writer.into_truncated());
 
             let act = tracelogging_dynamic::Guid::from_bytes_le(activity_id);
             let related = tracelogging_dynamic::Guid::from_bytes_le(related_activity_id);
@@ -241,6 +283,7 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        payload: &PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -259,15 +302,9 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
                 0,
             );
 
-            for f in fields {
-                <&mut EventBuilder as AddFieldAndValue>::add_field_value(
-                    &mut eb.deref_mut(),
-                    &FieldAndValue {
-                        field_name: f.field,
-                        value: &f.value,
-                    },
-                );
-            }
+            let mut writer = SpanFieldWriter::new(payload.field_budget(span_name));
+            writer.add_fields(&mut eb.deref_mut(), fields);
+            add_truncation_marker(&mut eb, &writer.into_truncated());
 
             let act = tracelogging_dynamic::Guid::from_bytes_le(activity_id);
             let related = tracelogging_dynamic::Guid::from_bytes_le(related_activity_id);
@@ -296,8 +333,10 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        options: &crate::_details::EventOptions,
         event: &tracing::Event<'_>,
         _otel_context: Option<([u8; 32], [u8; 16])>,
+        payload: &PayloadConfig,
     ) {
         let mut activity_id: [u8; 16] = *GLOBAL_ACTIVITY_SEED;
         activity_id[0] = if current_span != 0 {
@@ -321,7 +360,9 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
             let mut eb = eb.borrow_mut();
 
             eb.reset(event_name, Self::map_level(level), keyword, event_tag);
-            eb.opcode(Opcode::Info);
+            eb.opcode(Opcode::from_int(options.opcode));
+            eb.id_version(options.id, options.version);
+            eb.channel(Channel::from_int(options.channel));
 
             eb.add_systemtime(
                 "time",
@@ -330,7 +371,19 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
                 0,
             );
 
-            event.record(&mut EventBuilderVisitorWrapper::from(eb.deref_mut()));
+            let truncated = FMT_BUF.with(|buf| {
+                let mut buf = buf.borrow_mut();
+                let mut visitor = EventBuilderVisitorWrapper::new(
+                    eb.deref_mut(),
+                    &mut buf,
+                    payload.field_budget(event_name),
+                    payload.redactions.scope(event.metadata().target()),
+                );
+                event.record(&mut visitor);
+                visitor.into_truncated()
+            });
+
+            add_truncation_marker(&mut eb, &truncated);
 
             let act = tracelogging_dynamic::Guid::from_bytes_le(&activity_id);
             let related = tracelogging_dynamic::Guid::from_bytes_le(&related_activity_id);
@@ -351,34 +404,49 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
     }
 }
 
-struct CommonSchemaPartCBuilder<'a> {
+// Adds the fields of an event or span to one part (PartB or PartC) of a Common Schema event,
+// applying the layer's field mappings
+struct CommonSchemaFieldBuilder<'a, 'm> {
     eb: &'a mut EventBuilder,
+    mappings: &'m FieldMappings,
+    part: CommonSchemaPart,
 }
 
-impl<'a> CommonSchemaPartCBuilder<'a> {
-    fn make_visitor(
+impl<'a, 'm> CommonSchemaFieldBuilder<'a, 'm> {
+    fn make_visitor<'b>(
         eb: &'a mut EventBuilder,
-    ) -> EventBuilderVisitorWrapper<CommonSchemaPartCBuilder<'a>> {
-        EventBuilderVisitorWrapper::from(CommonSchemaPartCBuilder { eb })
+        mappings: &'m FieldMappings,
+        part: CommonSchemaPart,
+        scratch: &'b mut String,
+        budget: usize,
+        redactions: RedactionScope<'b>,
+    ) -> EventBuilderVisitorWrapper<'b, CommonSchemaFieldBuilder<'a, 'm>> {
+        EventBuilderVisitorWrapper::new(
+            CommonSchemaFieldBuilder { eb, mappings, part },
+            scratch,
+            budget,
+            redactions,
+        )
     }
 }
 
-impl AddFieldAndValue for CommonSchemaPartCBuilder<'_> {
+impl AddFieldAndValue for CommonSchemaFieldBuilder<'_, '_> {
     fn add_field_value(&mut self, fv: &FieldAndValue) {
-        let mut field_name: &'static str = fv.field_name;
-
-        if field_name == "message" {
-            field_name = "Body";
-            assert!(matches!(fv.value, ValueTypes::v_str(_)));
+        if let Some((part, field_name)) = self.mappings.place(fv.field_name) {
+            if part == self.part {
+                <&mut EventBuilder as AddFieldAndValue>::add_field_value(
+                    &mut self.eb,
+                    &FieldAndValue {
+                        field_name,
+                        value: fv.value,
+                    },
+                );
+            }
         }
+    }
 
-        <&mut EventBuilder as AddFieldAndValue>::add_field_value(
-            &mut self.eb,
-            &FieldAndValue {
-                field_name,
-                value: fv.value,
-            },
-        );
+    fn includes(&self, field_name: &str) -> bool {
+        self.mappings.is_in(field_name, self.part)
     }
 }
 
@@ -393,6 +461,7 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
         _level: &tracing_core::Level,
         _keyword: u64,
         _event_tag: u32,
+        _payload: &PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -408,6 +477,7 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        payload: &PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -478,8 +548,21 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
             //     }
             // }
 
+            let mappings = &payload.field_mappings;
+
+            // Fields without a value (never recorded, or dropped by a redaction rule) are not logged
+            let (partb_field_count, partc_field_count) = mappings.count_fields(
+                fields
+                    .iter()
+                    .filter(|f| !matches!(f.value, ValueTypes::None))
+                    .map(|f| f.field),
+            );
+
+            let mut writer = SpanFieldWriter::new(payload.field_budget(span_name));
+
             let span_parent = span.parent();
-            let partb_field_count = 3 + if span_parent.is_some() { 1 } else { 0 };
+            let partb_field_count =
+                3 + if span_parent.is_some() { 1 } else { 0 } + partb_field_count;
 
             eb.add_struct("PartB", partb_field_count, 0);
             {
@@ -525,25 +608,32 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
                     OutType::Utf8,
                     0,
                 );
+
+                writer.add_fields(
+                    &mut CommonSchemaFieldBuilder {
+                        eb: eb.deref_mut(),
+                        mappings,
+                        part: CommonSchemaPart::PartB,
+                    },
+                    fields,
+                );
             }
 
-            let partc_field_count = span.fields().len() as u8;
+            if partc_field_count != 0 {
+                eb.add_struct("PartC", partc_field_count, 0);
 
-            eb.add_struct("PartC", partc_field_count, 0);
-            {
-                let mut pfv = CommonSchemaPartCBuilder { eb: eb.deref_mut() };
-
-                for f in fields {
-                    <CommonSchemaPartCBuilder<'_> as AddFieldAndValue>::add_field_value(
-                        &mut pfv,
-                        &FieldAndValue {
-                            field_name: f.field,
-                            value: &f.value,
-                        },
-                    );
-                }
+                writer.add_fields(
+                    &mut CommonSchemaFieldBuilder {
+                        eb: eb.deref_mut(),
+                        mappings,
+                        part: CommonSchemaPart::PartC,
+                    },
+                    fields,
+                );
             }
 
+            add_truncation_marker(&mut eb, &writer.into_truncated());
+
             let _ = eb.write(&self.get_provider(), None, None);
         });
     }
@@ -557,14 +647,18 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        options: &crate::_details::EventOptions,
         event: &tracing::Event<'_>,
         otel_context: Option<([u8; 32], [u8; 16])>,
+        payload: &PayloadConfig,
     ) {
         EBW.with(|eb| {
             let mut eb = eb.borrow_mut();
 
             eb.reset(event_name, Self::map_level(level), keyword, event_tag);
-            eb.opcode(Opcode::Info);
+            eb.opcode(Opcode::from_int(options.opcode));
+            eb.id_version(options.id, options.version);
+            eb.channel(Channel::from_int(options.channel));
 
             // Promoting values from PartC to PartA extensions is apparently just a draft spec
             // and not necessary / supported by consumers.
@@ -603,7 +697,18 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
                 }
             }
 
-            eb.add_struct("PartB", 3, 0);
+            let redactions = payload.redactions.scope(event.metadata().target());
+            let mappings = &payload.field_mappings;
+
+            // Dropped fields are left out of the structs entirely
+            let (partb_field_count, partc_field_count) = mappings.count_fields(
+                event
+                    .fields()
+                    .map(|f| f.name())
+                    .filter(|name| !redactions.drops(name)),
+            );
+
+            eb.add_struct("PartB", 3 + partb_field_count, 0);
             {
                 eb.add_str8("_typeName", "Log", OutType::Utf8, 0);
                 eb.add_str8("name", event_name, OutType::Utf8, 0);
@@ -616,13 +721,34 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
                 );
             }
 
-            let partc_field_count = event.fields().count() as u8;
+            let truncated = FMT_BUF.with(|buf| {
+                let mut buf = buf.borrow_mut();
+                let mut visitor = CommonSchemaFieldBuilder::make_visitor(
+                    eb.deref_mut(),
+                    mappings,
+                    CommonSchemaPart::PartB,
+                    &mut buf,
+                    payload.field_budget(event_name),
+                    redactions,
+                );
 
-            eb.add_struct("PartC", partc_field_count, 0);
-            {
-                let mut visitor = CommonSchemaPartCBuilder::make_visitor(eb.deref_mut());
-                event.record(&mut visitor);
-            }
+                // The PartB struct is still open, so its mapped fields go at the end of it
+                if partb_field_count != 0 {
+                    event.record(&mut visitor);
+                }
+
+                if partc_field_count != 0 {
+                    let builder = visitor.wrapped_mut();
+                    builder.eb.add_struct("PartC", partc_field_count, 0);
+                    builder.part = CommonSchemaPart::PartC;
+
+                    event.record(&mut visitor);
+                }
+
+                visitor.into_truncated()
+            });
+
+            add_truncation_marker(&mut eb, &truncated);
 
             let _ = eb.write(&self.get_provider(), None, None);
         });
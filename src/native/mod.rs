@@ -6,9 +6,9 @@ pub mod etw;
 pub use etw::Provider;
 #[cfg(target_os = "windows")]
 pub(crate) use etw::ProviderGroupType;
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", not(feature = "runtime_metadata")))]
 pub(crate) use etw::_start__etw_kw;
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", not(feature = "runtime_metadata")))]
 pub(crate) use etw::_stop__etw_kw;
 
 #[cfg(not(any(target_os = "windows", target_os = "linux")))]
@@ -28,9 +28,11 @@ pub mod user_events;
 pub use user_events::Provider;
 #[cfg(target_os = "linux")]
 pub(crate) use user_events::ProviderGroupType;
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+pub(crate) mod modules;
+#[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
 pub(crate) use user_events::_start__etw_kw;
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
 pub(crate) use user_events::_stop__etw_kw;
 
 #[cfg(target_os = "linux")]
@@ -115,7 +117,7 @@ pub trait ProviderTraits {
         provider_name: &str,
         provider_id: &G,
         provider_group: &Option<ProviderGroupType>,
-        _default_keyword: u64,
+        _default_keywords: &[u64],
     ) -> std::pin::Pin<std::sync::Arc<Self>>
     where
         for<'a> &'a G: Into<GuidWrapper>;
@@ -142,6 +144,7 @@ pub trait EventWriter<OutMode: OutputMode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        payload: &crate::values::PayloadConfig,
     ) where
         R: tracing_subscriber::registry::LookupSpan<'a>;
 
@@ -156,6 +159,7 @@ pub trait EventWriter<OutMode: OutputMode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        payload: &crate::values::PayloadConfig,
     ) where
         R: tracing_subscriber::registry::LookupSpan<'a>;
 
@@ -169,7 +173,9 @@ pub trait EventWriter<OutMode: OutputMode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        options: &crate::_details::EventOptions,
         event: &tracing::Event<'_>,
         otel_context: Option<([u8; 32], [u8; 16])>, // (trace_id, span_id) when opentelemetry feature is enabled
+        payload: &crate::values::PayloadConfig,
     );
 }
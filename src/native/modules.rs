@@ -0,0 +1,221 @@
+// Finds the _etw_kw sections of the shared objects loaded into the process, including ones that
+// are loaded with dlopen after the process starts.
+//
+// Each shared object that contains etw_event! or etw_span! callsites has its own _etw_kw section,
+// and the linker exports the __start__etw_kw and __stop__etw_kw symbols that bound it. The
+// __start__etw_kw and __stop__etw_kw statics in user_events.rs only refer to the section of the
+// object this crate was linked into.
+
+use std::{
+    collections::HashSet,
+    ffi::{c_int, c_void, CString},
+    ops::Range,
+};
+
+use crate::_details::EventMetadata;
+
+// A loaded shared object that has not been scanned yet
+struct Module {
+    name: CString,
+    segments: Vec<Range<usize>>,
+}
+
+struct ModuleSearch<'a> {
+    scanned: &'a mut ScannedModules,
+    found: Vec<Module>,
+}
+
+// The loaded objects that have been scanned, including the main executable
+#[derive(Default)]
+pub(crate) struct ScannedModules {
+    bases: HashSet<usize>,
+    segments: Vec<Range<usize>>,
+}
+
+impl ScannedModules {
+    // True if the address is in an object that has been scanned, such as the address of a
+    // callsite static. The section of that object has then been read, so scanning again would
+    // not find the metadata of the callsite.
+    pub(crate) fn contains(&self, addr: usize) -> bool {
+        self.segments.iter().any(|segment| segment.contains(&addr))
+    }
+}
+
+// The number of shared objects that have been loaded into the process so far, including ones that
+// have since been unloaded. This changes whenever a new object is loaded.
+pub(crate) fn load_count() -> u64 {
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        _size: usize,
+        data: *mut c_void,
+    ) -> c_int {
+        // SAFETY info is valid for the duration of the callback, and data is the u64 passed below
+        unsafe { *(data as *mut u64) = (*info).dlpi_adds };
+        // Every entry has the same count, so there is no need to look at the rest
+        1
+    }
+
+    let mut adds: u64 = 0;
+    // SAFETY The callback only writes to adds, which outlives the call
+    unsafe { libc::dl_iterate_phdr(Some(callback), &mut adds as *mut u64 as *mut c_void) };
+    adds
+}
+
+// The contents of the _etw_kw sections of the loaded shared objects that are not in `scanned`.
+// The objects that were looked at are added to `scanned`.
+//
+// The main executable is skipped, as its section is found through __start__etw_kw and
+// __stop__etw_kw, and it does not usually export any symbols.
+//
+// The returned slices are only valid for as long as the objects stay loaded. Objects that contain
+// etw_event! or etw_span! callsites must never be unloaded, as tracing also keeps 'static
+// references to their callsites.
+pub(crate) fn new_module_sections(
+    scanned: &mut ScannedModules,
+) -> Vec<&'static [*const EventMetadata]> {
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        _size: usize,
+        data: *mut c_void,
+    ) -> c_int {
+        // SAFETY info is valid for the duration of the callback, and data is the ModuleSearch passed below
+        let (info, search) = unsafe { (&*info, &mut *(data as *mut ModuleSearch)) };
+
+        let base = info.dlpi_addr as usize;
+        if !search.scanned.bases.insert(base) {
+            return 0;
+        }
+
+        let segments: Vec<Range<usize>> = (0..info.dlpi_phnum as usize)
+            // SAFETY dlpi_phdr points to dlpi_phnum program headers
+            .map(|i| unsafe { &*info.dlpi_phdr.add(i) })
+            .filter(|phdr| phdr.p_type == libc::PT_LOAD)
+            .map(|phdr| {
+                let start = base + phdr.p_vaddr as usize;
+                start..start + phdr.p_memsz as usize
+            })
+            .collect();
+        search.scanned.segments.extend(segments.iter().cloned());
+
+        if info.dlpi_name.is_null() {
+            return 0;
+        }
+        // SAFETY dlpi_name is a nul-terminated string
+        let name = unsafe { std::ffi::CStr::from_ptr(info.dlpi_name) };
+        if name.is_empty() {
+            return 0;
+        }
+
+        search.found.push(Module {
+            name: name.to_owned(),
+            segments,
+        });
+        0
+    }
+
+    let mut search = ModuleSearch {
+        scanned,
+        found: Vec::new(),
+    };
+    // SAFETY The callback only accesses search, which outlives the call
+    unsafe {
+        libc::dl_iterate_phdr(
+            Some(callback),
+            &mut search as *mut ModuleSearch as *mut c_void,
+        )
+    };
+
+    // dlopen and dlsym cannot be called while dl_iterate_phdr holds the loader lock
+    search
+        .found
+        .iter()
+        .filter_map(|module| {
+            // SAFETY RTLD_NOLOAD only returns a handle to an object that is already loaded
+            let handle =
+                unsafe { libc::dlopen(module.name.as_ptr(), libc::RTLD_LAZY | libc::RTLD_NOLOAD) };
+            if handle.is_null() {
+                return None;
+            }
+
+            // SAFETY The handle is valid until dlclose
+            let (start, stop) = unsafe {
+                (
+                    libc::dlsym(handle, c"__start__etw_kw".as_ptr()) as usize,
+                    libc::dlsym(handle, c"__stop__etw_kw".as_ptr()) as usize,
+                )
+            };
+            // SAFETY The handle came from dlopen, and the object stays loaded as it was already loaded before
+            unsafe { libc::dlclose(handle) };
+
+            // dlsym also searches the dependencies of the object, which may have sections of their own
+            let in_module = |addr: usize| {
+                module
+                    .segments
+                    .iter()
+                    .any(|segment| segment.contains(&addr) || segment.end == addr)
+            };
+            if start == 0 || stop < start || !in_module(start) || !in_module(stop) {
+                return None;
+            }
+
+            let len = (stop - start) / size_of::<*const EventMetadata>();
+            // SAFETY The section only contains pointers, and is part of a loaded object
+            Some(unsafe { std::slice::from_raw_parts(start as *const *const EventMetadata, len) })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+
+    use super::{new_module_sections, ScannedModules};
+    use crate::statics::{event_metadata, get_event_metadata};
+
+    static LOCAL: u8 = 0;
+
+    #[test]
+    fn scan_test_binary() {
+        let mut scanned = ScannedModules::default();
+        assert!(!scanned.contains(&LOCAL as *const u8 as usize));
+
+        // The section of the test binary is read through __start__etw_kw instead, and the
+        // shared objects it links to have no sections
+        assert!(new_module_sections(&mut scanned).is_empty());
+        assert!(scanned.contains(&LOCAL as *const u8 as usize));
+
+        // Objects are only looked at once
+        let bases = scanned.bases.len();
+        assert!(new_module_sections(&mut scanned).is_empty());
+        assert_eq!(scanned.bases.len(), bases);
+    }
+
+    // Loads tests/fixtures/etw-module, a shared object with an etw_event! callsite.
+    // Build it first with `cargo build --manifest-path tests/fixtures/etw-module/Cargo.toml`,
+    // then run this test with `cargo test -- --ignored dlopen_module`.
+    // The event of the fixture uses keyword 0, so the sum in statics' test_ten is unaffected.
+    #[test]
+    #[ignore = "needs tests/fixtures/etw-module to be built"]
+    fn dlopen_module() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/etw-module/target/debug/libetw_module.so");
+        let path = CString::new(path.into_os_string().into_encoded_bytes()).unwrap();
+        let is_module_event = |event: &&crate::_details::EventMetadata| event.event_tag == 0xE7E7;
+
+        assert!(!event_metadata().any(|event| is_module_event(&event)));
+
+        let loads = super::load_count();
+        // SAFETY The fixture has no initializers. It is never closed, as tracing may keep
+        // references to its callsite.
+        let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW) };
+        assert!(!handle.is_null());
+        assert!(super::load_count() > loads);
+
+        let event = event_metadata().find(is_module_event).unwrap();
+        // SAFETY The description was written by the etw_event! macro, from a static str
+        assert_eq!(unsafe { event.description.name.as_str() }, "ModuleEvent");
+        assert!(
+            get_event_metadata(&event.identity).is_some_and(|found| core::ptr::eq(found, event))
+        );
+    }
+}
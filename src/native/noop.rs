@@ -36,7 +36,7 @@ impl<Mode: OutputMode> crate::native::ProviderTraits for Provider<Mode> {
         _provider_name: &str,
         _provider_id: &G,
         _provider_group: &Option<ProviderGroupType>,
-        _default_keyword: u64,
+        _default_keywords: &[u64],
     ) -> Pin<Arc<Self>>
     where
         for<'a> &'a G: Into<crate::native::GuidWrapper>,
@@ -56,6 +56,7 @@ impl<OutMode: OutputMode> crate::native::EventWriter<OutMode> for Provider<OutMo
         _level: &tracing_core::Level,
         _keyword: u64,
         _event_tag: u32,
+        _payload: &crate::values::PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -71,6 +72,7 @@ impl<OutMode: OutputMode> crate::native::EventWriter<OutMode> for Provider<OutMo
         _level: &tracing_core::Level,
         _keyword: u64,
         _event_tag: u32,
+        _payload: &crate::values::PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -85,8 +87,10 @@ impl<OutMode: OutputMode> crate::native::EventWriter<OutMode> for Provider<OutMo
         _level: &tracing_core::Level,
         _keyword: u64,
         _event_tag: u32,
+        _options: &crate::_details::EventOptions,
         _event: &tracing::Event<'_>,
         _otel_context: Option<([u8; 32], [u8; 16])>,
+        _payload: &crate::values::PayloadConfig,
     ) {
     }
 }
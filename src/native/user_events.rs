@@ -1,8 +1,11 @@
 use crate::{
     error::EtwError,
+    field_mapping::{CommonSchemaPart, FieldMappings},
+    formats::FormattedValue,
     native::{CommonSchemaOutput, NormalOutput, OutputMode},
+    redaction::RedactionScope,
     statics::GLOBAL_ACTIVITY_SEED,
-    values::{event_values::*, *},
+    values::{event_values::*, span_values::SpanFieldWriter, *},
 };
 use eventheader::*;
 use eventheader_dynamic::EventBuilder;
@@ -19,6 +22,7 @@ use tracing_subscriber::registry::{LookupSpan, SpanRef};
 pub(crate) type ProviderGroupType = std::borrow::Cow<'static, str>;
 
 // These are automagically generated by the compiler for the start and stop of section _etw_kw
+#[cfg(not(feature = "runtime_metadata"))]
 unsafe extern "C" {
     #[link_name = "__start__etw_kw"]
     pub(crate) static _start__etw_kw: usize;
@@ -26,11 +30,16 @@ unsafe extern "C" {
     pub(crate) static _stop__etw_kw: usize;
 }
 
+#[cfg(not(feature = "runtime_metadata"))]
 #[unsafe(link_section = "_etw_kw")]
 #[used]
 static mut ETW_META_PTR: *const crate::_details::EventMetadata = core::ptr::null();
 
-thread_local! {static EBW: std::cell::RefCell<EventBuilder>  = RefCell::new(EventBuilder::new());}
+thread_local! {
+    static EBW: std::cell::RefCell<EventBuilder> = RefCell::new(EventBuilder::new());
+    // Debug-formatted values (including every event message) are written here before being added to the event
+    static FMT_BUF: std::cell::RefCell<String> = const { RefCell::new(String::new()) };
+}
 
 impl AddFieldAndValue for &'_ mut eventheader_dynamic::EventBuilder {
     fn add_field_value(&mut self, fv: &FieldAndValue) {
@@ -60,10 +69,47 @@ impl AddFieldAndValue for &'_ mut eventheader_dynamic::EventBuilder {
             ValueTypes::v_char(c) => {
                 self.add_value(fv.field_name, *c, FieldFormat::StringUtf, 0);
             }
+            ValueTypes::v_formatted(f) => match *f {
+                FormattedValue::Hex(u) => {
+                    self.add_value(fv.field_name, u, FieldFormat::HexInt, 0);
+                }
+                FormattedValue::Ipv4(addr) => {
+                    self.add_value(fv.field_name, addr, FieldFormat::IPAddress, 0);
+                }
+                FormattedValue::Ipv6(addr) => {
+                    self.add_value(fv.field_name, addr, FieldFormat::IPAddress, 0);
+                }
+                FormattedValue::Guid(u) => {
+                    self.add_value(fv.field_name, u.to_be_bytes(), FieldFormat::Uuid, 0);
+                }
+                FormattedValue::Errno(i) => {
+                    self.add_value(fv.field_name, i, FieldFormat::Errno, 0);
+                }
+                FormattedValue::Pid(u) => {
+                    self.add_value(fv.field_name, u, FieldFormat::Pid, 0);
+                }
+                FormattedValue::Hresult(i) => {
+                    self.add_value(fv.field_name, i, FieldFormat::HexInt, 0);
+                }
+            },
         }
     }
 }
 
+// Logs the original length of any values that were truncated to fit in the event, as a
+// `_truncated` struct with one field per truncated value.
+fn add_truncation_marker(eb: &mut EventBuilder, truncated: &[(&'static str, u64)]) {
+    if truncated.is_empty() {
+        return;
+    }
+
+    let count = truncated.len().min(crate::values::MAX_TRUNCATED_FIELDS);
+    eb.add_struct("_truncated", count as u8, 0);
+    for (field_name, original_len) in &truncated[..count] {
+        eb.add_value(field_name, *original_len, FieldFormat::Default, 0);
+    }
+}
+
 #[doc(hidden)]
 pub struct Provider<OutMode: OutputMode> {
     provider: std::sync::RwLock<eventheader_dynamic::Provider>,
@@ -109,18 +155,25 @@ impl<OutMode: OutputMode> crate::native::ProviderTraits for Provider<OutMode> {
             .read()
             .unwrap()
             .find_set(Self::map_level(level), keyword);
-        if let Some(s) = es {
-            s.enabled()
-        } else {
-            false
-        }
+        // The keyword of a callsite that had not run yet (with runtime_metadata), or that is in a
+        // shared object that was loaded after the provider was created, has not been registered.
+        // Nor has a keyword set with a ReloadHandle. Registering takes the write lock and creates
+        // a tracepoint, but only once for each level and keyword: the keywords passed here all come
+        // from the metadata of a callsite or from the layer's settings.
+        let es = es.unwrap_or_else(|| {
+            self.provider
+                .write()
+                .unwrap()
+                .register_set(Self::map_level(level), keyword)
+        });
+        es.enabled()
     }
 
     fn new<G>(
         provider_name: &str,
         _: &G,
         provider_group: &Option<ProviderGroupType>,
-        default_keyword: u64,
+        default_keywords: &[u64],
     ) -> Pin<Arc<Self>>
     where
         for<'a> &'a G: Into<crate::native::GuidWrapper>,
@@ -140,11 +193,14 @@ impl<OutMode: OutputMode> crate::native::ProviderTraits for Provider<OutMode> {
             provider.register_set(Self::map_level(&tracing::Level::TRACE), event.kw);
         }
 
-        provider.register_set(Self::map_level(&tracing::Level::ERROR), default_keyword);
-        provider.register_set(Self::map_level(&tracing::Level::WARN), default_keyword);
-        provider.register_set(Self::map_level(&tracing::Level::INFO), default_keyword);
-        provider.register_set(Self::map_level(&tracing::Level::DEBUG), default_keyword);
-        provider.register_set(Self::map_level(&tracing::Level::TRACE), default_keyword);
+        // The keywords used for events that do not have their own
+        for &keyword in default_keywords {
+            provider.register_set(Self::map_level(&tracing::Level::ERROR), keyword);
+            provider.register_set(Self::map_level(&tracing::Level::WARN), keyword);
+            provider.register_set(Self::map_level(&tracing::Level::INFO), keyword);
+            provider.register_set(Self::map_level(&tracing::Level::DEBUG), keyword);
+            provider.register_set(Self::map_level(&tracing::Level::TRACE), keyword);
+        }
 
         Arc::pin(Self {
             provider: std::sync::RwLock::new(provider),
@@ -202,6 +258,7 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        payload: &PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -229,15 +286,9 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
                 0,
             );
 
-            for f in fields {
-                <&mut EventBuilder as AddFieldAndValue>::add_field_value(
-                    &mut eb.deref_mut(),
-                    &FieldAndValue {
-                        field_name: f.field,
-                        value: &f.value,
-                    },
-                );
-            }
+            let mut writer = SpanFieldWriter::new(payload.field_budget(span_name));
+            writer.add_fields(&mut eb.deref_mut(), fields);
+            add_truncation_marker(&mut eb, &writer.into_truncated());
 
             let _ = eb.write(
                 &es,
@@ -265,6 +316,7 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        payload: &PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -293,15 +345,9 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
                 0,
             );
 
-            for f in fields {
-                <&mut EventBuilder as AddFieldAndValue>::add_field_value(
-                    &mut eb.deref_mut(),
-                    &FieldAndValue {
-                        field_name: f.field,
-                        value: &f.value,
-                    },
-                );
-            }
+            let mut writer = SpanFieldWriter::new(payload.field_budget(span_name));
+            writer.add_fields(&mut eb.deref_mut(), fields);
+            add_truncation_marker(&mut eb, &writer.into_truncated());
 
             let _ = eb.write(
                 &es,
@@ -328,8 +374,10 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        options: &crate::_details::EventOptions,
         event: &tracing::Event<'_>,
         _otel_context: Option<([u8; 32], [u8; 16])>,
+        payload: &PayloadConfig,
     ) {
         let es = if let Some(es) = self.find_set(Self::map_level(level), keyword) {
             es
@@ -359,7 +407,8 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
             let mut eb = eb.borrow_mut();
 
             eb.reset(event_name, event_tag as u16);
-            eb.opcode(Opcode::Info);
+            eb.opcode(Opcode::from_int(options.opcode));
+            eb.id_version(options.id, options.version);
 
             eb.add_value(
                 "time",
@@ -371,8 +420,19 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
                 0,
             );
 
-            let mut visitor = EventBuilderVisitorWrapper::from(eb.deref_mut());
-            event.record(&mut visitor);
+            let truncated = FMT_BUF.with(|buf| {
+                let mut buf = buf.borrow_mut();
+                let mut visitor = EventBuilderVisitorWrapper::new(
+                    eb.deref_mut(),
+                    &mut buf,
+                    payload.field_budget(event_name),
+                    payload.redactions.scope(event.metadata().target()),
+                );
+                event.record(&mut visitor);
+                visitor.into_truncated()
+            });
+
+            add_truncation_marker(&mut eb, &truncated);
 
             let _ = eb.write(
                 &es,
@@ -391,34 +451,49 @@ impl<Mode: OutputMode> super::EventWriter<NormalOutput> for Provider<Mode> {
     }
 }
 
-struct CommonSchemaPartCBuilder<'a> {
+// Adds the fields of an event or span to one part (PartB or PartC) of a Common Schema event,
+// applying the layer's field mappings
+struct CommonSchemaFieldBuilder<'a, 'm> {
     eb: &'a mut EventBuilder,
+    mappings: &'m FieldMappings,
+    part: CommonSchemaPart,
 }
 
-impl<'a> CommonSchemaPartCBuilder<'a> {
-    fn make_visitor(
+impl<'a, 'm> CommonSchemaFieldBuilder<'a, 'm> {
+    fn make_visitor<'b>(
         eb: &'a mut EventBuilder,
-    ) -> EventBuilderVisitorWrapper<CommonSchemaPartCBuilder<'a>> {
-        EventBuilderVisitorWrapper::from(CommonSchemaPartCBuilder { eb })
+        mappings: &'m FieldMappings,
+        part: CommonSchemaPart,
+        scratch: &'b mut String,
+        budget: usize,
+        redactions: RedactionScope<'b>,
+    ) -> EventBuilderVisitorWrapper<'b, CommonSchemaFieldBuilder<'a, 'm>> {
+        EventBuilderVisitorWrapper::new(
+            CommonSchemaFieldBuilder { eb, mappings, part },
+            scratch,
+            budget,
+            redactions,
+        )
     }
 }
 
-impl AddFieldAndValue for CommonSchemaPartCBuilder<'_> {
+impl AddFieldAndValue for CommonSchemaFieldBuilder<'_, '_> {
     fn add_field_value(&mut self, fv: &FieldAndValue) {
-        let mut field_name: &'static str = fv.field_name;
-
-        if field_name == "message" {
-            field_name = "Body";
-            assert!(matches!(fv.value, ValueTypes::v_str(_)));
+        if let Some((part, field_name)) = self.mappings.place(fv.field_name) {
+            if part == self.part {
+                <&mut EventBuilder as AddFieldAndValue>::add_field_value(
+                    &mut self.eb,
+                    &FieldAndValue {
+                        field_name,
+                        value: fv.value,
+                    },
+                );
+            }
         }
+    }
 
-        <&mut EventBuilder as AddFieldAndValue>::add_field_value(
-            &mut self.eb,
-            &FieldAndValue {
-                field_name,
-                value: fv.value,
-            },
-        );
+    fn includes(&self, field_name: &str) -> bool {
+        self.mappings.is_in(field_name, self.part)
     }
 }
 
@@ -433,6 +508,7 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
         _level: &tracing_core::Level,
         _keyword: u64,
         _event_tag: u32,
+        _payload: &PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -448,6 +524,7 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        payload: &PayloadConfig,
     ) where
         R: LookupSpan<'a>,
     {
@@ -524,8 +601,21 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
             //     }
             // }
 
+            let mappings = &payload.field_mappings;
+
+            // Fields without a value (never recorded, or dropped by a redaction rule) are not logged
+            let (partb_field_count, partc_field_count) = mappings.count_fields(
+                fields
+                    .iter()
+                    .filter(|f| !matches!(f.value, ValueTypes::None))
+                    .map(|f| f.field),
+            );
+
+            let mut writer = SpanFieldWriter::new(payload.field_budget(span_name));
+
             let span_parent = span.parent();
-            let partb_field_count = 3 + if span_parent.is_some() { 1 } else { 0 };
+            let partb_field_count =
+                3 + if span_parent.is_some() { 1 } else { 0 } + partb_field_count;
 
             eb.add_struct("PartB", partb_field_count, 0);
             {
@@ -565,31 +655,38 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
 
                 eb.add_str(
                     "startTime",
-                    &chrono::DateTime::to_rfc3339(&chrono::DateTime::<chrono::Utc>::from(
+                    chrono::DateTime::to_rfc3339(&chrono::DateTime::<chrono::Utc>::from(
                         start_stop_times.0,
                     )),
                     FieldFormat::Default,
                     0,
                 );
+
+                writer.add_fields(
+                    &mut CommonSchemaFieldBuilder {
+                        eb: eb.deref_mut(),
+                        mappings,
+                        part: CommonSchemaPart::PartB,
+                    },
+                    fields,
+                );
             }
 
-            let partc_field_count = span.fields().len() as u8;
+            if partc_field_count != 0 {
+                eb.add_struct("PartC", partc_field_count, 0);
 
-            eb.add_struct("PartC", partc_field_count, 0);
-            {
-                let mut pfv = CommonSchemaPartCBuilder { eb: eb.deref_mut() };
-
-                for f in fields {
-                    <CommonSchemaPartCBuilder<'_> as AddFieldAndValue>::add_field_value(
-                        &mut pfv,
-                        &FieldAndValue {
-                            field_name: f.field,
-                            value: &f.value,
-                        },
-                    );
-                }
+                writer.add_fields(
+                    &mut CommonSchemaFieldBuilder {
+                        eb: eb.deref_mut(),
+                        mappings,
+                        part: CommonSchemaPart::PartC,
+                    },
+                    fields,
+                );
             }
 
+            add_truncation_marker(&mut eb, &writer.into_truncated());
+
             let _ = eb.write(&es, None, None);
         });
     }
@@ -603,8 +700,10 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
         level: &tracing_core::Level,
         keyword: u64,
         event_tag: u32,
+        options: &crate::_details::EventOptions,
         event: &tracing::Event<'_>,
         otel_context: Option<([u8; 32], [u8; 16])>,
+        payload: &PayloadConfig,
     ) {
         let es = if let Some(es) = self.find_set(Self::map_level(level), keyword) {
             es
@@ -616,7 +715,8 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
             let mut eb = eb.borrow_mut();
 
             eb.reset(event_name, event_tag as u16);
-            eb.opcode(Opcode::Info);
+            eb.opcode(Opcode::from_int(options.opcode));
+            eb.id_version(options.id, options.version);
 
             // Promoting values from PartC to PartA extensions is apparently just a draft spec
             // and not necessary / supported by consumers.
@@ -655,28 +755,58 @@ impl<Mode: OutputMode> super::EventWriter<CommonSchemaOutput> for Provider<Mode>
                 }
             }
 
-            eb.add_struct("PartB", 3, 0);
+            let redactions = payload.redactions.scope(event.metadata().target());
+            let mappings = &payload.field_mappings;
+
+            // Dropped fields are left out of the structs entirely
+            let (partb_field_count, partc_field_count) = mappings.count_fields(
+                event
+                    .fields()
+                    .map(|f| f.name())
+                    .filter(|name| !redactions.drops(name)),
+            );
+
+            eb.add_struct("PartB", 3 + partb_field_count, 0);
             {
                 eb.add_str("_typeName", "Log", FieldFormat::Default, 0);
                 eb.add_str("name", event_name, FieldFormat::Default, 0);
 
                 eb.add_str(
                     "eventTime",
-                    &chrono::DateTime::to_rfc3339(&chrono::DateTime::<chrono::Utc>::from(
-                        timestamp,
-                    )),
+                    chrono::DateTime::to_rfc3339(&chrono::DateTime::<chrono::Utc>::from(timestamp)),
                     FieldFormat::Default,
                     0,
                 );
             }
 
-            let partc_field_count = event.fields().count() as u8;
+            let truncated = FMT_BUF.with(|buf| {
+                let mut buf = buf.borrow_mut();
+                let mut visitor = CommonSchemaFieldBuilder::make_visitor(
+                    eb.deref_mut(),
+                    mappings,
+                    CommonSchemaPart::PartB,
+                    &mut buf,
+                    payload.field_budget(event_name),
+                    redactions,
+                );
 
-            eb.add_struct("PartC", partc_field_count, 0);
-            {
-                let mut visitor = CommonSchemaPartCBuilder::make_visitor(eb.deref_mut());
-                event.record(&mut visitor);
-            }
+                // The PartB struct is still open, so its mapped fields go at the end of it
+                if partb_field_count != 0 {
+                    event.record(&mut visitor);
+                }
+
+                if partc_field_count != 0 {
+                    let builder = visitor.wrapped_mut();
+                    builder.eb.add_struct("PartC", partc_field_count, 0);
+                    builder.part = CommonSchemaPart::PartC;
+
+                    event.record(&mut visitor);
+                }
+
+                visitor.into_truncated()
+            });
+
+            add_truncation_marker(&mut eb, &truncated);
 
             let _ = eb.write(&es, None, None);
         });
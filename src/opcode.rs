@@ -0,0 +1,40 @@
+/// The opcode of an event logged with the [etw_event!][crate::etw_event] macro.
+///
+/// Opcodes mark an event as a step in a larger operation, such as a request
+/// being sent and its reply being received. Events logged without an opcode use [Opcode::Info].
+/// Spans always use the start and stop opcodes for the events logged when they are
+/// entered and exited.
+///
+/// ETW and user_events assign the same values to the predefined opcodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Opcode(u8);
+
+#[allow(non_upper_case_globals)]
+impl Opcode {
+    /// An informational event that is not part of an activity.
+    pub const Info: Opcode = Opcode(0);
+    /// The start of an activity.
+    pub const Start: Opcode = Opcode(1);
+    /// The end of an activity.
+    pub const Stop: Opcode = Opcode(2);
+    /// A reply to a request.
+    pub const Reply: Opcode = Opcode(6);
+    /// An activity that was suspended is resuming.
+    pub const Resume: Opcode = Opcode(7);
+    /// An activity is being suspended.
+    pub const Suspend: Opcode = Opcode(8);
+    /// A message or request was sent.
+    pub const Send: Opcode = Opcode(9);
+    /// A message or request was received.
+    pub const Receive: Opcode = Opcode(240);
+
+    /// Returns an opcode with the given value, such as one defined by a custom manifest.
+    pub const fn from_int(value: u8) -> Opcode {
+        Opcode(value)
+    }
+
+    /// Returns the value of this opcode.
+    pub const fn as_int(self) -> u8 {
+        self.0
+    }
+}
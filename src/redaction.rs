@@ -0,0 +1,157 @@
+use std::{
+    fmt::{self, Write},
+    hash::Hasher,
+};
+
+use hashers::fnv::FNV1aHasher64;
+
+/// How the value of a redacted field is replaced before it is written to an event.
+///
+/// Redaction rules are registered with [LayerBuilder::with_field_redaction][crate::LayerBuilder::with_field_redaction]
+/// and [LayerBuilder::with_target_redaction][crate::LayerBuilder::with_target_redaction].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Redaction {
+    /// Leave the field out of the event entirely.
+    Drop,
+    /// Replace the value with a 64-bit FNV-1a hash of the value, logged as a hex number.
+    /// This allows values to be correlated across events without logging them directly.
+    ///
+    /// This is not a cryptographic hash. Values from a small or predictable set,
+    /// such as phone numbers, can be recovered by hashing every candidate value;
+    /// use [Redaction::Mask] or [Redaction::Drop] for these.
+    Hash,
+    /// Replace the value with a fixed string, such as `"***"`.
+    Mask(String),
+}
+
+// The redaction rules for a single layer
+#[derive(Clone, Default)]
+pub(crate) struct RedactionRules {
+    fields: Vec<(Box<str>, Redaction)>,
+    targets: Vec<(Box<str>, Redaction)>,
+}
+
+impl RedactionRules {
+    pub(crate) fn add_field(&mut self, field_name: &str, redaction: Redaction) {
+        Self::insert(&mut self.fields, field_name, redaction);
+    }
+
+    pub(crate) fn add_target(&mut self, target: &str, redaction: Redaction) {
+        Self::insert(&mut self.targets, target, redaction);
+    }
+
+    fn insert(rules: &mut Vec<(Box<str>, Redaction)>, name: &str, redaction: Redaction) {
+        if let Some(rule) = rules.iter_mut().find(|(n, _)| &**n == name) {
+            rule.1 = redaction;
+        } else {
+            rules.push((name.into(), redaction));
+        }
+    }
+
+    // Resolves the rules that apply to an event or span with the given target
+    pub(crate) fn scope(&self, target: &str) -> RedactionScope<'_> {
+        let target_rule = self
+            .targets
+            .iter()
+            .filter(|(prefix, _)| target_matches(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, redaction)| redaction);
+
+        RedactionScope {
+            fields: &self.fields,
+            target: target_rule,
+        }
+    }
+}
+
+// A target rule applies to the target itself and to any module path nested within it
+pub(crate) fn target_matches(target: &str, prefix: &str) -> bool {
+    match target.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+// The redaction rules that apply to the fields of a single event or span.
+// A rule for a specific field takes precedence over a rule for the whole target.
+#[derive(Clone, Copy)]
+pub(crate) struct RedactionScope<'a> {
+    fields: &'a [(Box<str>, Redaction)],
+    target: Option<&'a Redaction>,
+}
+
+impl<'a> RedactionScope<'a> {
+    #[inline]
+    pub(crate) fn get(&self, field_name: &str) -> Option<&'a Redaction> {
+        self.fields
+            .iter()
+            .find(|(name, _)| &**name == field_name)
+            .map(|(_, redaction)| redaction)
+            .or(self.target)
+    }
+
+    pub(crate) fn drops(&self, field_name: &str) -> bool {
+        matches!(self.get(field_name), Some(Redaction::Drop))
+    }
+}
+
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = FNV1aHasher64::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+struct HashWriter(FNV1aHasher64);
+
+impl Write for HashWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+// Hashes the Debug representation of a value without buffering it
+pub(crate) fn hash_debug(value: &dyn fmt::Debug) -> u64 {
+    let mut writer = HashWriter(FNV1aHasher64::default());
+    let _ = write!(writer, "{:?}", value);
+    writer.0.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn field_rule_overrides_target_rule() {
+        let mut rules = RedactionRules::default();
+        rules.add_target("myapp::auth", Redaction::Drop);
+        rules.add_field("email", Redaction::Hash);
+
+        let scope = rules.scope("myapp::auth::login");
+        assert_eq!(scope.get("email"), Some(&Redaction::Hash));
+        assert_eq!(scope.get("token"), Some(&Redaction::Drop));
+
+        let scope = rules.scope("myapp::authz");
+        assert_eq!(scope.get("email"), Some(&Redaction::Hash));
+        assert_eq!(scope.get("token"), None);
+    }
+
+    #[test]
+    fn longest_target_wins() {
+        let mut rules = RedactionRules::default();
+        rules.add_target("myapp", Redaction::Hash);
+        rules.add_target("myapp::auth", Redaction::Mask("***".to_string()));
+        rules.add_target("myapp", Redaction::Drop);
+
+        assert_eq!(rules.scope("myapp::db").get("x"), Some(&Redaction::Drop));
+        assert_eq!(
+            rules.scope("myapp::auth").get("x"),
+            Some(&Redaction::Mask("***".to_string()))
+        );
+    }
+}
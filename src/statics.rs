@@ -1,10 +1,16 @@
 // Module for static variables that are used by the crate.
 
-use std::{cmp, hash::BuildHasher, iter::FusedIterator, sync::LazyLock};
-
-use crate::_details::{EventMetadata, ParsedEventMetadata};
-
-type FnvHasher = std::hash::BuildHasherDefault<hashers::fnv::FNV1aHasher64>;
+#[cfg(any(feature = "runtime_metadata", target_os = "linux"))]
+use std::sync::RwLock;
+use std::{
+    iter::FusedIterator,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        LazyLock,
+    },
+};
+
+use crate::_details::{EventMetadata, KeywordDefinition};
 
 pub(crate) static GLOBAL_ACTIVITY_SEED: LazyLock<[u8; 16]> = LazyLock::new(|| {
     let now = std::time::SystemTime::now()
@@ -19,15 +25,20 @@ pub(crate) static GLOBAL_ACTIVITY_SEED: LazyLock<[u8; 16]> = LazyLock::new(|| {
     data
 });
 
-#[cfg(any(target_os = "windows", target_os = "linux"))]
-fn process_static_metadata() -> Box<[ParsedEventMetadata]> {
-    // The array of pointers are in a mutable section and can be sorted/deduped, but they are pointing to read-only static data
+#[cfg(all(
+    any(target_os = "windows", target_os = "linux"),
+    not(feature = "runtime_metadata")
+))]
+fn process_static_metadata() -> StaticRecords {
+    // The array of pointers is shared with other versions of this crate in the same binary,
+    // which may be reading it at the same time, so it is copied rather than sorted in place.
+    // The pointers are pointing to read-only static data.
 
-    let start = &raw const crate::native::_start__etw_kw as *mut *const EventMetadata;
-    let stop = &raw const crate::native::_stop__etw_kw as *mut *const EventMetadata;
+    let start = &raw const crate::native::_start__etw_kw as *const *const EventMetadata;
+    let stop = &raw const crate::native::_stop__etw_kw as *const *const EventMetadata;
 
     if start.is_null() {
-        return Box::new([]);
+        return StaticRecords::default();
     }
 
     // SAFETY On Windows the start and stop entries are sentry values at the start and end of the linker section.
@@ -39,115 +50,316 @@ fn process_static_metadata() -> Box<[ParsedEventMetadata]> {
 
     // SAFETY Start is not null and points to a valid static in memory (else the code wouldn't link),
     // so we can guarantee we aren't making a reference to null here.
-    let events_slice = unsafe { &mut *core::ptr::slice_from_raw_parts_mut(start, stop_offset) };
-
-    if events_slice.is_empty() || // On Windows, an empty binary produces an empty array
-       (events_slice.len() == 1 && events_slice[0].is_null()) { // On Linux, an empty binary produces a single array element of 0x0.
-        return Box::new([]);
+    let events_slice = unsafe { core::slice::from_raw_parts(start, stop_offset) };
+
+    // On Windows, an empty binary produces an empty array. On Linux, an empty binary
+    // produces a single array element of 0x0. Spurious nulls and duplicate pointers are removed.
+    let mut events: Vec<*const EventMetadata> = events_slice
+        .iter()
+        .copied()
+        .filter(|event| !event.is_null())
+        .collect();
+    events.sort_unstable();
+    events.dedup();
+
+    let mut records = StaticRecords {
+        events: Vec::with_capacity(events.len()),
+        keywords: Vec::new(),
+    };
+    for event in events {
+        // SAFETY The above code has already removed the null pointers, and the others point
+        // to records written by some version of this crate.
+        unsafe { records.add(event) };
     }
 
-    // Sort spurious nulls to the end. This is comparing pointers as usize, not their pointed-to values.
-    events_slice.sort_unstable_by(|a, b| b.cmp(a));
+    records
+}
 
-    // Remove spurious duplicate pointers
-    let end_pos = events_slice.len();
-    let mut good_pos = 0;
-    while good_pos != end_pos - 1 {
-        if events_slice[good_pos] == events_slice[good_pos + 1] {
-            let mut next_pos = good_pos + 2;
-            while next_pos != end_pos {
-                if events_slice[next_pos].is_null() {
-                    break;
-                }
-                if events_slice[good_pos] != events_slice[next_pos] {
-                    good_pos += 1;
-                    events_slice[good_pos] = events_slice[next_pos];
-                }
-                next_pos += 1;
-            }
-            break;
+// The records read from a linker section
+#[cfg(not(feature = "runtime_metadata"))]
+#[derive(Default)]
+struct StaticRecords {
+    events: Vec<&'static EventMetadata>,
+    keywords: Vec<&'static KeywordDefinition>,
+}
+
+#[cfg(all(
+    any(target_os = "windows", target_os = "linux"),
+    not(feature = "runtime_metadata")
+))]
+impl StaticRecords {
+    // Other versions of this crate in the same binary share the linker section, and their records
+    // may have a different layout. Those records are skipped, and are read by their own version instead.
+    //
+    // SAFETY The pointer must be non-null and point to a record written by some version of this crate,
+    // all of which are at least as large and as aligned as a RecordHeader.
+    unsafe fn add(&mut self, record: *const EventMetadata) {
+        use crate::_details::RecordHeader;
+
+        let header = unsafe { record.cast::<RecordHeader>().read() };
+        if header == RecordHeader::CURRENT {
+            self.events.push(unsafe { &*record });
+        } else if header == RecordHeader::KEYWORD {
+            self.keywords
+                .push(unsafe { &*record.cast::<KeywordDefinition>() });
         }
-        if events_slice[good_pos + 1].is_null() {
-            break;
+    }
+}
+
+#[cfg(all(target_os = "windows", not(feature = "runtime_metadata")))]
+static STATIC_RECORDS: LazyLock<StaticRecords> = LazyLock::new(process_static_metadata);
+
+#[cfg(all(
+    not(any(target_os = "windows", target_os = "linux")),
+    not(feature = "runtime_metadata")
+))]
+static STATIC_RECORDS: StaticRecords = StaticRecords {
+    events: Vec::new(),
+    keywords: Vec::new(),
+};
+
+// An open-addressed hash table of all the EventMetadata, keyed by the address of the callsite.
+// The table is sized so that a lookup usually only compares a single entry.
+//
+// Entries are only ever added, never removed or moved, so the table can be read without a lock
+// while a single writer inserts into it.
+struct MetadataTable {
+    slots: Box<[AtomicPtr<EventMetadata>]>,
+    shift: u32,
+}
+
+impl MetadataTable {
+    fn new(events: &[&'static EventMetadata]) -> Self {
+        let capacity = (events.len() * 2).next_power_of_two().max(2);
+        let table = MetadataTable {
+            slots: (0..capacity)
+                .map(|_| AtomicPtr::new(core::ptr::null_mut()))
+                .collect(),
+            shift: u64::BITS - capacity.trailing_zeros(),
+        };
+
+        for meta in events {
+            table.insert(meta);
         }
-        good_pos += 1;
+
+        table
     }
 
-    // Explicitly set all the values at the end to null
-    let mut next_pos = good_pos + 1;
-    while next_pos != end_pos {
-        events_slice[next_pos] = core::ptr::null();
-        next_pos += 1;
+    // The table must have at least one empty slot left after the insert,
+    // and only one thread may insert at a time
+    fn insert(&self, meta: &'static EventMetadata) {
+        let mask = self.slots.len() - 1;
+        let mut idx = self.slot(&meta.identity);
+        while !self.slots[idx].load(Ordering::Relaxed).is_null() {
+            idx = (idx + 1) & mask;
+        }
+        self.slots[idx].store(meta as *const _ as *mut _, Ordering::Release);
     }
 
-    let bh = FnvHasher::default();
+    // Fibonacci hashing of the callsite's address.
+    // Identifier's Hash implementation also hashes the vtable pointer, which is not guaranteed
+    // to be the same for every reference to the same callsite, so it isn't used here.
+    #[inline]
+    fn slot(&self, id: &tracing::callsite::Identifier) -> usize {
+        let addr = id.0 as *const dyn tracing::Callsite as *const () as usize as u64;
+        (addr.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> self.shift) as usize
+    }
 
-    let mut vec = Vec::with_capacity(good_pos + 1);
-    next_pos = 0;
-    while next_pos <= good_pos {
-        // SAFETY The above code as already validated that events_slice[0..good_pos] are non-null pointers
-        let next = unsafe { &*events_slice[next_pos] };
-        let identity_hash = bh.hash_one(&next.identity);
-        vec.push(ParsedEventMetadata {
-            identity_hash,
-            meta: next,
-        });
-        next_pos += 1;
+    #[inline]
+    fn get(&self, id: &tracing::callsite::Identifier) -> Option<&'static EventMetadata> {
+        let mask = self.slots.len() - 1;
+        let mut idx = self.slot(id);
+        // The table is never full, so there is always an empty slot to end the search
+        loop {
+            let meta = self.slots[idx].load(Ordering::Acquire);
+            if meta.is_null() {
+                return None;
+            }
+            // SAFETY Non-null slots only hold references to static EventMetadata
+            let meta = unsafe { &*meta };
+            if meta.identity == *id {
+                return Some(meta);
+            }
+            idx = (idx + 1) & mask;
+        }
     }
+}
+
+#[cfg(all(not(target_os = "linux"), not(feature = "runtime_metadata")))]
+static METADATA_TABLE: LazyLock<MetadataTable> =
+    LazyLock::new(|| MetadataTable::new(&STATIC_RECORDS.events));
+
+#[cfg(all(not(target_os = "linux"), not(feature = "runtime_metadata")))]
+pub(crate) fn get_event_metadata(
+    id: &tracing::callsite::Identifier,
+) -> Option<&'static crate::_details::EventMetadata> {
+    METADATA_TABLE.get(id)
+}
 
-    let mut sorted = vec.into_boxed_slice();
-    sorted.sort_unstable_by(|a, b| b.cmp(a));
-    sorted
+// With the runtime_metadata feature, etw_event! and etw_span! register their metadata here
+// the first time they are run, rather than leaving it in a linker section.
+// On Linux, the metadata in the linker sections of shared objects is added here as they are loaded.
+#[cfg(any(feature = "runtime_metadata", target_os = "linux"))]
+struct MetadataRegistry {
+    events: Vec<&'static EventMetadata>,
+    // The table that is published in CURRENT_TABLE
+    table: &'static MetadataTable,
+    keywords: Vec<&'static KeywordDefinition>,
+    // The number of shared objects that had been loaded at the last scan, and the ones that
+    // were scanned
+    #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+    module_loads: u64,
+    #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+    scanned_modules: crate::native::modules::ScannedModules,
 }
 
-#[cfg(any(target_os = "windows", target_os = "linux"))]
-static EVENT_METADATA: LazyLock<Box<[ParsedEventMetadata]>> =
-    LazyLock::new(process_static_metadata);
+#[cfg(any(feature = "runtime_metadata", target_os = "linux"))]
+impl MetadataRegistry {
+    fn insert(&mut self, meta: &'static EventMetadata) {
+        if self.table.get(&meta.identity).is_some() {
+            return;
+        }
+
+        self.events.push(meta);
+        // Keep the table at most half full, as when it is built from the linker section
+        if self.events.len() * 2 > self.table.slots.len() {
+            self.publish(MetadataTable::new(&self.events));
+        } else {
+            self.table.insert(meta);
+        }
+    }
 
-#[cfg(not(any(target_os = "windows", target_os = "linux")))]
-static EVENT_METADATA: [ParsedEventMetadata; 0] = [];
+    // The replaced table is leaked, as readers may still be using it without holding the lock.
+    // Each table is twice the size of the one it replaces, so the leaked tables are never larger
+    // than the current one in total.
+    fn publish(&mut self, table: MetadataTable) {
+        self.table = Box::leak(Box::new(table));
+        CURRENT_TABLE.store(
+            self.table as *const MetadataTable as *mut MetadataTable,
+            Ordering::Release,
+        );
+    }
 
-impl core::cmp::PartialEq for ParsedEventMetadata {
-    fn eq(&self, other: &Self) -> bool {
-        cmp::Ordering::Equal == self.cmp(other)
+    #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+    fn add_records(&mut self, records: StaticRecords) {
+        for meta in records.events {
+            self.insert(meta);
+        }
+        for keyword in records.keywords {
+            if !self.keywords.iter().any(|k| core::ptr::eq(*k, keyword)) {
+                self.keywords.push(keyword);
+            }
+        }
     }
-}
 
-impl core::cmp::Eq for ParsedEventMetadata {}
+    #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+    fn scan_loaded_modules(&mut self) {
+        self.module_loads = crate::native::modules::load_count();
+        for section in crate::native::modules::new_module_sections(&mut self.scanned_modules) {
+            // Unlike the section of this binary, the section is not sorted in place,
+            // as the copy of this crate in the shared object may be reading it too.
+            let mut records = StaticRecords::default();
+            for &record in section {
+                // SAFETY Non-null entries in the section point to records written by some version of this crate
+                if !record.is_null() {
+                    unsafe { records.add(record) };
+                }
+            }
+            self.add_records(records);
+        }
+    }
+}
 
-impl core::cmp::PartialOrd for ParsedEventMetadata {
-    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        Some(self.cmp(other))
+#[cfg(any(feature = "runtime_metadata", target_os = "linux"))]
+static REGISTRY: LazyLock<RwLock<MetadataRegistry>> = LazyLock::new(|| {
+    #[allow(unused_mut)]
+    let mut registry = MetadataRegistry {
+        events: Vec::new(),
+        table: Box::leak(Box::new(MetadataTable::new(&[]))),
+        keywords: Vec::new(),
+        #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+        module_loads: 0,
+        #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+        scanned_modules: Default::default(),
+    };
+
+    #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+    {
+        registry.add_records(process_static_metadata());
+        registry.scan_loaded_modules();
     }
+
+    CURRENT_TABLE.store(
+        registry.table as *const MetadataTable as *mut MetadataTable,
+        Ordering::Release,
+    );
+    RwLock::new(registry)
+});
+
+// The table of the registry, which is read without taking the registry's lock.
+// Null until the registry is first used.
+#[cfg(any(feature = "runtime_metadata", target_os = "linux"))]
+static CURRENT_TABLE: AtomicPtr<MetadataTable> = AtomicPtr::new(core::ptr::null_mut());
+
+#[cfg(feature = "runtime_metadata")]
+pub(crate) fn register_event_metadata(meta: &'static EventMetadata) {
+    REGISTRY.write().unwrap().insert(meta);
 }
 
-// Order by hash only
-impl core::cmp::Ord for ParsedEventMetadata {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.identity_hash.cmp(&other.identity_hash)
+// Scans the shared objects that were loaded since the last scan, e.g. with dlopen
+#[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+fn refresh_loaded_modules() {
+    let loads = crate::native::modules::load_count();
+    if REGISTRY.read().unwrap().module_loads != loads {
+        REGISTRY.write().unwrap().scan_loaded_modules();
     }
 }
 
+#[cfg(any(feature = "runtime_metadata", target_os = "linux"))]
 pub(crate) fn get_event_metadata(
     id: &tracing::callsite::Identifier,
 ) -> Option<&'static crate::_details::EventMetadata> {
-    let bh = FnvHasher::default();
-    let identity_hash = bh.hash_one(id);
-    let idx = EVENT_METADATA.partition_point(|other| other.identity_hash > identity_hash);
-    let mut cur = idx;
-    while cur < EVENT_METADATA.len() {
-        let meta = &EVENT_METADATA[cur];
-        if meta.identity_hash != identity_hash {
+    let mut table = CURRENT_TABLE.load(Ordering::Acquire);
+    if table.is_null() {
+        LazyLock::force(&REGISTRY);
+        table = CURRENT_TABLE.load(Ordering::Acquire);
+    }
+    // SAFETY Published tables are leaked, and so are never freed
+    unsafe { &*table }.get(id)
+}
+
+// Like get_event_metadata, but a callsite that is not found may be in a shared object that was
+// loaded since the last scan. Used when a callsite is registered, before any of its events are logged.
+//
+// Most callsites that are not found are not etw_event! or etw_span! callsites at all, so the
+// loaded objects are only scanned again if the callsite is not in an object that was scanned.
+pub(crate) fn find_event_metadata(
+    id: &tracing::callsite::Identifier,
+) -> Option<&'static crate::_details::EventMetadata> {
+    let meta = get_event_metadata(id);
+    #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+    if meta.is_none() {
+        let addr = id.0 as *const dyn tracing::Callsite as *const () as usize;
+        if REGISTRY.read().unwrap().scanned_modules.contains(addr) {
             return None;
         }
+        refresh_loaded_modules();
+        return get_event_metadata(id);
+    }
+    meta
+}
 
-        if meta.meta.identity == *id {
-            return Some(meta.meta);
-        }
+// The keywords defined with etw_keywords!, which are never registered with runtime_metadata
+pub(crate) fn keyword_definitions() -> Vec<&'static KeywordDefinition> {
+    #[cfg(any(feature = "runtime_metadata", target_os = "linux"))]
+    {
+        #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+        refresh_loaded_modules();
 
-        cur += 1;
+        REGISTRY.read().unwrap().keywords.clone()
     }
-    None
+    #[cfg(all(not(target_os = "linux"), not(feature = "runtime_metadata")))]
+    STATIC_RECORDS.keywords.clone()
 }
 
 pub(crate) struct EventMetadataEnumerator {
@@ -159,12 +371,26 @@ impl FusedIterator for EventMetadataEnumerator {}
 impl Iterator for EventMetadataEnumerator {
     type Item = &'static EventMetadata;
 
+    #[cfg(all(not(target_os = "linux"), not(feature = "runtime_metadata")))]
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current_index >= EVENT_METADATA.len() {
+        if self.current_index >= STATIC_RECORDS.events.len() {
             return None;
         }
 
-        let result = &EVENT_METADATA[self.current_index].meta;
+        let result = STATIC_RECORDS.events[self.current_index];
+
+        self.current_index += 1;
+
+        Some(result)
+    }
+
+    // Callsites that are registered while iterating are included, until the iterator ends
+    #[cfg(any(feature = "runtime_metadata", target_os = "linux"))]
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(&result) = REGISTRY.read().unwrap().events.get(self.current_index) else {
+            self.current_index = usize::MAX;
+            return None;
+        };
 
         self.current_index += 1;
 
@@ -172,19 +398,19 @@ impl Iterator for EventMetadataEnumerator {
     }
 }
 
-#[allow(dead_code)]
-// Currently only used on Linux targets and the tests
-pub(crate) fn event_metadata() -> impl Iterator<Item = <EventMetadataEnumerator as Iterator>::Item>
-{
+pub(crate) fn event_metadata() -> EventMetadataEnumerator {
+    #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+    refresh_loaded_modules();
+
     EventMetadataEnumerator { current_index: 0 }
 }
 
-// Only one test function can be compiled into the module at a time, since the statics the macro produces are global
+// The statics the macro produces are global, so every test in this module sees the events from all of them
 #[cfg(test)]
 mod test {
     use tracing::Level;
 
-    use crate::{etw_event, statics::event_metadata};
+    use crate::{_details::EventOptions, etw_event, statics::event_metadata, Opcode};
 
     // #[test]
     // fn test_none() {
@@ -233,4 +459,101 @@ mod test {
 
         assert_eq!(sum, expected);
     }
+
+    // Uses keyword 0 so the sum in test_ten is unaffected
+    #[test]
+    fn test_arbitrary_names() {
+        etw_event!(target: module_path!(), name: "Db.Query", Level::INFO, 0, 100, { rows = 1 });
+        etw_event!(target: module_path!(), name: "Db Query-2", Level::INFO, 0, 101, { rows = 2 });
+        etw_event!(target: module_path!(), name: "Db.Query", Level::INFO, 0, 102, { rows = 3 });
+
+        let count = event_metadata()
+            .filter(|event| (100..=102).contains(&event.event_tag))
+            .count();
+
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        let expected = 3;
+        #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+        let expected = 0;
+
+        assert_eq!(count, expected);
+    }
+
+    #[test]
+    fn test_options() {
+        etw_event!(target: module_path!(), name: "Request.Send", opcode: Opcode::Send, version: 2, channel: 16, id: 40, Level::INFO, 0, 103, { id = 1 });
+        etw_event!(name: "Request.Reply", version: 1, opcode: Opcode::Reply, Level::INFO, 0, "Got a reply");
+        etw_event!(target: module_path!(), name: "Request.Done", Level::INFO, 0, 104, { id = 1 });
+
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        {
+            let options = |tag| {
+                event_metadata()
+                    .find(|event| event.event_tag == tag)
+                    .map(|event| event.options)
+            };
+
+            assert_eq!(
+                options(103),
+                Some(EventOptions {
+                    opcode: 9,
+                    version: 2,
+                    channel: 16,
+                    id: 40
+                })
+            );
+            assert_eq!(options(104), Some(EventOptions::DEFAULT));
+            assert!(
+                event_metadata().any(|event| event.options.opcode == Opcode::Reply.as_int()
+                    && event.options.version == 1)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        etw_event!(target: module_path!(), name: "Lookup", Level::INFO, 0, 105, { id = 1 });
+
+        // Every registered callsite resolves to its own metadata
+        for event in event_metadata() {
+            let found = super::get_event_metadata(&event.identity);
+            assert!(found.is_some_and(|found| core::ptr::eq(found, event)));
+        }
+
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
+        assert!(event_metadata().any(|event| event.event_tag == 105));
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "runtime_metadata")))]
+    #[test]
+    fn test_foreign_records() {
+        use crate::_details::RecordHeader;
+
+        // A record from a version of the crate without a header, which starts with its keyword
+        static FOREIGN: [u64; 8] = [0x8000, 1, 2, 3, 4, 5, 6, 7];
+        #[unsafe(link_section = "_etw_kw")]
+        #[used]
+        static mut FOREIGN_PTR: *const [u64; 8] = &FOREIGN;
+
+        assert!(!event_metadata().any(|event| core::ptr::addr_eq(event, &FOREIGN)));
+        assert!(event_metadata().all(|event| event.header == RecordHeader::CURRENT));
+    }
+
+    #[cfg(feature = "runtime_metadata")]
+    #[test]
+    fn test_runtime_registration() {
+        fn log() {
+            etw_event!(target: module_path!(), name: "Registered", Level::INFO, 0, 106, { id = 1 });
+        }
+
+        assert!(!event_metadata().any(|event| event.event_tag == 106));
+        log();
+        log();
+        assert_eq!(
+            event_metadata()
+                .filter(|event| event.event_tag == 106)
+                .count(),
+            1
+        );
+    }
 }
@@ -0,0 +1,236 @@
+use std::{collections::HashMap, fmt};
+
+use tracing::Level;
+
+/// A problem with the metadata of an `etw_event!` or `etw_span!` callsite that would make
+/// the event hard or impossible to collect.
+///
+/// Problems are found by [validate_event_metadata][crate::validate_event_metadata], or
+/// when a layer is built with [LayerBuilder::with_event_metadata_validation][crate::LayerBuilder::with_event_metadata_validation].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MetadataIssue {
+    /// Events with the same name use different keywords.
+    ConflictingKeywords {
+        name: &'static str,
+        keywords: (u64, u64),
+    },
+    /// Events with the same name use different levels.
+    ConflictingLevels {
+        name: &'static str,
+        levels: (Level, Level),
+    },
+    /// The event has keyword 0, which ETW sessions that filter on keywords never collect.
+    /// Only reported on Windows.
+    ZeroKeyword { name: &'static str },
+    /// The keyword sets bits in the high 16 bits, which are reserved by Microsoft.
+    /// Only reported on Windows.
+    ReservedKeywordBits { name: &'static str, keyword: u64 },
+    /// The tag does not fit in the 16 bits that user_events keeps.
+    /// Only reported on Linux.
+    TagTooWide { name: &'static str, tag: u32 },
+}
+
+impl fmt::Display for MetadataIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataIssue::ConflictingKeywords { name, keywords } => write!(
+                f,
+                "event {name:?} is logged with keywords {} and {}",
+                KeywordDisplay(keywords.0),
+                KeywordDisplay(keywords.1)
+            ),
+            MetadataIssue::ConflictingLevels { name, levels } => write!(
+                f,
+                "event {name:?} is logged at levels {} and {}",
+                levels.0, levels.1
+            ),
+            MetadataIssue::ZeroKeyword { name } => write!(f, "event {name:?} has keyword 0"),
+            MetadataIssue::ReservedKeywordBits { name, keyword } => write!(
+                f,
+                "event {name:?} has keyword {}, which uses the reserved high 16 bits",
+                KeywordDisplay(*keyword)
+            ),
+            MetadataIssue::TagTooWide { name, tag } => write!(
+                f,
+                "event {name:?} has tag {tag:#x}, which is wider than 16 bits"
+            ),
+        }
+    }
+}
+
+// A keyword, followed by the names of the keywords defined with etw_keywords! that it sets
+struct KeywordDisplay(u64);
+
+impl fmt::Display for KeywordDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)?;
+        let names = crate::catalog::keyword_names(self.0);
+        if !names.is_empty() {
+            write!(f, " ({})", names.join(" | "))?;
+        }
+        Ok(())
+    }
+}
+
+// The parts of an EventMetadata that are validated
+struct CallsiteInfo {
+    name: &'static str,
+    level: Level,
+    kw: u64,
+    tag: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Checks {
+    etw: bool,
+    user_events: bool,
+}
+
+const RESERVED_KEYWORD_BITS: u64 = 0xFFFF_0000_0000_0000;
+
+fn find_issues(
+    callsites: impl Iterator<Item = CallsiteInfo>,
+    checks: Checks,
+) -> Vec<MetadataIssue> {
+    let mut issues = Vec::new();
+    // The first keyword and level seen for each name, and whether a conflict was already reported
+    let mut names: HashMap<&'static str, (u64, Level, bool, bool)> = HashMap::new();
+
+    for callsite in callsites {
+        let name = callsite.name;
+
+        if checks.etw && callsite.kw == 0 {
+            issues.push(MetadataIssue::ZeroKeyword { name });
+        }
+        if checks.etw && callsite.kw & RESERVED_KEYWORD_BITS != 0 {
+            issues.push(MetadataIssue::ReservedKeywordBits {
+                name,
+                keyword: callsite.kw,
+            });
+        }
+        if checks.user_events && callsite.tag > u16::MAX as u32 {
+            issues.push(MetadataIssue::TagTooWide {
+                name,
+                tag: callsite.tag,
+            });
+        }
+
+        let (kw, level, kw_reported, level_reported) =
+            names
+                .entry(name)
+                .or_insert((callsite.kw, callsite.level, false, false));
+        if *kw != callsite.kw && !*kw_reported {
+            *kw_reported = true;
+            issues.push(MetadataIssue::ConflictingKeywords {
+                name,
+                keywords: (*kw, callsite.kw),
+            });
+        }
+        if *level != callsite.level && !*level_reported {
+            *level_reported = true;
+            issues.push(MetadataIssue::ConflictingLevels {
+                name,
+                levels: (*level, callsite.level),
+            });
+        }
+    }
+
+    issues
+}
+
+/// Check the metadata of every `etw_event!` and `etw_span!` callsite in the binary for
+/// problems that would make events hard or impossible to collect. See [MetadataIssue].
+///
+/// An empty list is returned if no problems were found.
+///
+/// Layers never run this check on their own, unless they are built with
+/// [LayerBuilder::with_event_metadata_validation][crate::LayerBuilder::with_event_metadata_validation].
+/// To report problems as warnings instead, call this once the program's subscriber is set up.
+///
+/// ```
+/// for issue in tracing_etw::validate_event_metadata() {
+///     eprintln!("warning: {issue}");
+/// }
+/// ```
+pub fn validate_event_metadata() -> Vec<MetadataIssue> {
+    let callsites = crate::event_catalog().map(|event| CallsiteInfo {
+        name: event.name(),
+        level: event.level(),
+        kw: event.keyword(),
+        tag: event.tag(),
+    });
+
+    find_issues(
+        callsites,
+        Checks {
+            etw: cfg!(target_os = "windows"),
+            user_events: cfg!(target_os = "linux"),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn callsite(name: &'static str, level: Level, kw: u64, tag: u32) -> CallsiteInfo {
+        CallsiteInfo {
+            name,
+            level,
+            kw,
+            tag,
+        }
+    }
+
+    #[test]
+    fn finds_issues() {
+        let callsites = [
+            callsite("Db.Query", Level::INFO, 0x10, 1),
+            callsite("Db.Query", Level::WARN, 0x20, 2),
+            callsite("Db.Query", Level::ERROR, 0x40, 3),
+            callsite("Zero", Level::INFO, 0, 0),
+            callsite("Reserved", Level::INFO, 0x1_0000_0000_0001, 0),
+            callsite("Wide", Level::INFO, 1, 0x1_0000),
+        ];
+
+        let issues = find_issues(
+            callsites.into_iter(),
+            Checks {
+                etw: true,
+                user_events: true,
+            },
+        );
+        assert_eq!(
+            issues,
+            vec![
+                MetadataIssue::ConflictingKeywords {
+                    name: "Db.Query",
+                    keywords: (0x10, 0x20)
+                },
+                MetadataIssue::ConflictingLevels {
+                    name: "Db.Query",
+                    levels: (Level::INFO, Level::WARN)
+                },
+                MetadataIssue::ZeroKeyword { name: "Zero" },
+                MetadataIssue::ReservedKeywordBits {
+                    name: "Reserved",
+                    keyword: 0x1_0000_0000_0001
+                },
+                MetadataIssue::TagTooWide {
+                    name: "Wide",
+                    tag: 0x1_0000
+                },
+            ]
+        );
+
+        let issues = find_issues(
+            [callsite("Zero", Level::INFO, 0, 0x1_0000)].into_iter(),
+            Checks {
+                etw: false,
+                user_events: false,
+            },
+        );
+        assert!(issues.is_empty());
+    }
+}
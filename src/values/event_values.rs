@@ -2,88 +2,186 @@ use std::fmt::Write;
 
 use tracing::field;
 
-use crate::values::*;
+use crate::{
+    formats::FormattedValue,
+    redaction::{hash_bytes, hash_debug, Redaction, RedactionScope},
+    values::*,
+};
 
 // Implemented on the EventBuilder types
 #[allow(dead_code)]
 pub(crate) trait AddFieldAndValue {
     fn add_field_value(&mut self, fv: &crate::values::FieldAndValue);
+
+    // Whether the field should be passed to add_field_value at all
+    fn includes(&self, _field_name: &str) -> bool {
+        true
+    }
 }
 
 // We need a wrapper because we cannot implement an external trait (field::Visit) on an external type (EventBuilder)
 #[allow(dead_code)]
-pub(crate) struct EventBuilderVisitorWrapper<T: AddFieldAndValue> {
+pub(crate) struct EventBuilderVisitorWrapper<'b, T: AddFieldAndValue> {
     wrapped: T,
+    // Reusable scratch space for formatting Debug values, so they can be passed to the builder as a borrowed &str
+    scratch: &'b mut String,
+    // Bytes left for field names and values before strings start being truncated
+    budget: usize,
+    // Fields whose values were truncated, along with their original length
+    truncated: TruncatedFields,
+    redactions: RedactionScope<'b>,
 }
 
-// Construct a wrapper from the EventBuilder type
-impl<T: AddFieldAndValue> From<T> for EventBuilderVisitorWrapper<T> {
-    fn from(value: T) -> Self {
-        EventBuilderVisitorWrapper { wrapped: value }
+impl<'b, T: AddFieldAndValue> EventBuilderVisitorWrapper<'b, T> {
+    // Construct a wrapper from the EventBuilder type, a (usually thread-local) formatting buffer,
+    // the number of bytes the fields of the event may use, and the redaction rules for the event
+    pub(crate) fn new(
+        wrapped: T,
+        scratch: &'b mut String,
+        budget: usize,
+        redactions: RedactionScope<'b>,
+    ) -> Self {
+        EventBuilderVisitorWrapper {
+            wrapped,
+            scratch,
+            budget,
+            truncated: TruncatedFields::default(),
+            redactions,
+        }
+    }
+
+    pub(crate) fn wrapped_mut(&mut self) -> &mut T {
+        &mut self.wrapped
+    }
+
+    // The fields that were truncated to fit the budget, to be logged as a marker once all fields are added
+    pub(crate) fn into_truncated(self) -> Vec<(&'static str, u64)> {
+        self.truncated.into_vec()
+    }
+
+    // Room left for a string value in the field with the given name
+    fn str_budget(&self, field_name: &str) -> usize {
+        self.budget.saturating_sub(field_name.len() + 3)
+    }
+
+    fn add(&mut self, field_name: &'static str, value: &ValueTypes) {
+        self.budget = self
+            .budget
+            .saturating_sub(field_name.len() + 1 + value.encoded_size());
+
+        self.wrapped
+            .add_field_value(&FieldAndValue { field_name, value })
+    }
+
+    // Skips fields the wrapped builder does not include, and applies the redaction rule
+    // for the field if there is one.
+    // Returns true if the field was skipped or redacted and should not be logged normally.
+    fn skip_or_redact(&mut self, field_name: &'static str, hash: impl FnOnce() -> u64) -> bool {
+        if !self.wrapped.includes(field_name) {
+            return true;
+        }
+
+        let redactions = self.redactions;
+        match redactions.get(field_name) {
+            None => return false,
+            Some(Redaction::Drop) => (),
+            Some(Redaction::Mask(mask)) => self.add(field_name, &ValueTypes::from(mask.as_str())),
+            Some(Redaction::Hash) => {
+                self.add(field_name, &ValueTypes::from(FormattedValue::Hex(hash())))
+            }
+        }
+        true
+    }
+
+    fn add_str(&mut self, field_name: &'static str, value: &str, original_len: usize) {
+        let value = self
+            .truncated
+            .fit(&mut self.budget, field_name, value, original_len);
+
+        self.add(field_name, &ValueTypes::from(value));
     }
 }
 
-impl<T: AddFieldAndValue> field::Visit for EventBuilderVisitorWrapper<T> {
+impl<T: AddFieldAndValue> field::Visit for EventBuilderVisitorWrapper<'_, T> {
     fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
-        let mut string = String::with_capacity(10);
-        if write!(string, "{:?}", value).is_err() {
-            // TODO: Needs to do a heap allocation
+        if self.skip_or_redact(field.name(), || hash_debug(value)) {
             return;
         }
 
-        self.wrapped.add_field_value(&FieldAndValue {
-            field_name: field.name(),
-            value: &ValueTypes::from(string),
-        })
+        // The buffer keeps its capacity between events, so this only allocates when a value
+        // is larger than anything previously formatted on this thread.
+        let mut scratch = std::mem::take(self.scratch);
+        scratch.clear();
+
+        crate::formats::begin_format();
+        let mut writer = TruncatingWriter::new(&mut scratch, self.str_budget(field.name()));
+        let formatted = write!(writer, "{:?}", value).is_ok();
+        let original_len = writer.total_len();
+
+        if formatted {
+            match crate::formats::end_format(original_len) {
+                Some(formatted) => self.add(field.name(), &ValueTypes::from(formatted)),
+                None => self.add_str(field.name(), &scratch, original_len),
+            }
+        }
+
+        *self.scratch = scratch;
     }
 
     fn record_f64(&mut self, field: &field::Field, value: f64) {
-        self.wrapped.add_field_value(&FieldAndValue {
-            field_name: field.name(),
-            value: &ValueTypes::from(value),
-        })
+        if self.skip_or_redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
+        self.add(field.name(), &ValueTypes::from(value))
     }
 
     fn record_i64(&mut self, field: &field::Field, value: i64) {
-        self.wrapped.add_field_value(&FieldAndValue {
-            field_name: field.name(),
-            value: &ValueTypes::from(value),
-        })
+        if self.skip_or_redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
+        self.add(field.name(), &ValueTypes::from(value))
     }
 
     fn record_u64(&mut self, field: &field::Field, value: u64) {
-        self.wrapped.add_field_value(&FieldAndValue {
-            field_name: field.name(),
-            value: &ValueTypes::from(value),
-        })
+        if self.skip_or_redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
+        self.add(field.name(), &ValueTypes::from(value))
     }
 
     fn record_i128(&mut self, field: &field::Field, value: i128) {
-        self.wrapped.add_field_value(&FieldAndValue {
-            field_name: field.name(),
-            value: &ValueTypes::from(value),
-        })
+        if self.skip_or_redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
+        self.add(field.name(), &ValueTypes::from(value))
     }
 
     fn record_u128(&mut self, field: &field::Field, value: u128) {
-        self.wrapped.add_field_value(&FieldAndValue {
-            field_name: field.name(),
-            value: &ValueTypes::from(value),
-        })
+        if self.skip_or_redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
+        self.add(field.name(), &ValueTypes::from(value))
     }
 
     fn record_bool(&mut self, field: &field::Field, value: bool) {
-        self.wrapped.add_field_value(&FieldAndValue {
-            field_name: field.name(),
-            value: &ValueTypes::from(value),
-        })
+        if self.skip_or_redact(field.name(), || hash_bytes(&[value as u8])) {
+            return;
+        }
+
+        self.add(field.name(), &ValueTypes::from(value))
     }
 
     fn record_str(&mut self, field: &field::Field, value: &str) {
-        self.wrapped.add_field_value(&FieldAndValue {
-            field_name: field.name(),
-            value: &ValueTypes::from(value.to_string()),
-        })
+        if self.skip_or_redact(field.name(), || hash_bytes(value.as_bytes())) {
+            return;
+        }
+
+        self.add_str(field.name(), value, value.len())
     }
 
     fn record_error(&mut self, _field: &field::Field, _value: &(dyn std::error::Error + 'static)) {}
@@ -1,12 +1,14 @@
 pub(crate) mod event_values;
 pub(crate) mod span_values;
 
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt};
+
+use crate::{field_mapping::FieldMappings, formats::FormattedValue, redaction::RedactionRules};
 
 #[allow(non_camel_case_types, dead_code)]
 #[derive(Default, Clone)]
 #[doc(hidden)]
-pub enum ValueTypes {
+pub enum ValueTypes<'a> {
     #[default]
     None,
     v_u64(u64),
@@ -15,68 +17,330 @@ pub enum ValueTypes {
     v_i128(i128),
     v_f64(f64),
     v_bool(bool),
-    v_str(Cow<'static, str>), // Would be nice if we didn't have to do a heap allocation
+    v_str(Cow<'a, str>), // Borrowed for event fields, owned for span fields that outlive the callsite
     v_char(char),
+    v_formatted(FormattedValue),
 }
 
-impl From<u64> for ValueTypes {
+impl From<u64> for ValueTypes<'_> {
     fn from(value: u64) -> Self {
         ValueTypes::v_u64(value)
     }
 }
 
-impl From<i64> for ValueTypes {
+impl From<i64> for ValueTypes<'_> {
     fn from(value: i64) -> Self {
         ValueTypes::v_i64(value)
     }
 }
 
-impl From<u128> for ValueTypes {
+impl From<u128> for ValueTypes<'_> {
     fn from(value: u128) -> Self {
         ValueTypes::v_u128(value)
     }
 }
 
-impl From<i128> for ValueTypes {
+impl From<i128> for ValueTypes<'_> {
     fn from(value: i128) -> Self {
         ValueTypes::v_i128(value)
     }
 }
 
-impl From<f64> for ValueTypes {
+impl From<f64> for ValueTypes<'_> {
     fn from(value: f64) -> Self {
         ValueTypes::v_f64(value)
     }
 }
 
-impl From<bool> for ValueTypes {
+impl From<bool> for ValueTypes<'_> {
     fn from(value: bool) -> Self {
         ValueTypes::v_bool(value)
     }
 }
 
-impl From<&'static str> for ValueTypes {
-    fn from(value: &'static str) -> Self {
+impl<'a> From<&'a str> for ValueTypes<'a> {
+    fn from(value: &'a str) -> Self {
         ValueTypes::v_str(Cow::from(value))
     }
 }
 
-impl From<String> for ValueTypes {
+impl From<String> for ValueTypes<'_> {
     fn from(value: String) -> Self {
         ValueTypes::v_str(Cow::from(value))
     }
 }
 
-impl From<char> for ValueTypes {
+impl From<char> for ValueTypes<'_> {
     fn from(value: char) -> Self {
         ValueTypes::v_char(value)
     }
 }
 
+impl From<FormattedValue> for ValueTypes<'_> {
+    fn from(value: FormattedValue) -> Self {
+        ValueTypes::v_formatted(value)
+    }
+}
+
 #[allow(dead_code)]
 pub(crate) struct FieldAndValue<'a> {
     #[allow(dead_code)]
-    pub(crate) field_name: &'static str,
+    pub(crate) field_name: &'a str,
     #[allow(dead_code)]
-    pub(crate) value: &'a ValueTypes,
+    pub(crate) value: &'a ValueTypes<'a>,
+}
+
+// Default budget for the fields of a single event. Both ETW and user_events drop events
+// larger than 64KB, and some of that space is needed for the event header and metadata.
+pub(crate) const DEFAULT_MAX_EVENT_SIZE: usize = 60 * 1024;
+
+// Default cap for each string value stored for the lifetime of a span.
+pub(crate) const DEFAULT_MAX_SPAN_VALUE_SIZE: usize = 4 * 1024;
+
+// Bytes reserved from the event budget for the event name, timestamp, and Common Schema
+// envelope fields.
+const RESERVED_EVENT_SIZE: usize = 512;
+
+// The most fields the `_truncated` marker struct can have, as the field count of a struct is 7 bits.
+pub(crate) const MAX_TRUNCATED_FIELDS: usize = 127;
+
+// Bytes the `_truncated` marker struct takes in the payload, not including its fields.
+const TRUNCATION_MARKER_SIZE: usize = "_truncated".len() + 3;
+
+// Per-layer settings that control how field values are written to the event payload.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct PayloadConfig {
+    pub(crate) max_event_size: usize,
+    pub(crate) max_span_value_size: usize,
+    pub(crate) redactions: RedactionRules,
+    pub(crate) field_mappings: FieldMappings,
+}
+
+impl Default for PayloadConfig {
+    fn default() -> Self {
+        PayloadConfig {
+            max_event_size: DEFAULT_MAX_EVENT_SIZE,
+            max_span_value_size: DEFAULT_MAX_SPAN_VALUE_SIZE,
+            redactions: RedactionRules::default(),
+            field_mappings: FieldMappings::default(),
+        }
+    }
+}
+
+impl PayloadConfig {
+    // The number of bytes available for the fields of an event with the given name
+    pub(crate) fn field_budget(&self, event_name: &str) -> usize {
+        self.max_event_size
+            .saturating_sub(RESERVED_EVENT_SIZE + event_name.len())
+    }
+}
+
+impl ValueTypes<'_> {
+    // Approximate number of bytes this value takes in the payload, not including the field name.
+    pub(crate) fn encoded_size(&self) -> usize {
+        match self {
+            ValueTypes::None => 0,
+            ValueTypes::v_u64(_) | ValueTypes::v_i64(_) | ValueTypes::v_f64(_) => 8,
+            ValueTypes::v_u128(_) | ValueTypes::v_i128(_) => 16,
+            ValueTypes::v_bool(_) | ValueTypes::v_char(_) => 4,
+            ValueTypes::v_str(s) => s.len() + 2,
+            ValueTypes::v_formatted(_) => 16,
+        }
+    }
+}
+
+// Shortens a string to at most `max_len` bytes without splitting a UTF-8 sequence.
+pub(crate) fn truncate_str(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+
+    let mut len = max_len;
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    &s[..len]
+}
+
+// The fields whose values were truncated to fit the budget of an event, along with their original
+// length, to be logged as a `_truncated` marker struct once all fields are added. The marker is
+// counted against the same budget as the fields, so that it cannot push the event over the limit.
+#[derive(Default)]
+pub(crate) struct TruncatedFields {
+    fields: Vec<(&'static str, u64)>,
+}
+
+impl TruncatedFields {
+    // Shortens a string value so that it, and its entry in the marker, fit in the budget, and takes
+    // the size of the entry out of the budget. The size of the value itself is left to the caller.
+    // `value` may already be shorter than `original_len`.
+    pub(crate) fn fit<'a>(
+        &mut self,
+        budget: &mut usize,
+        field_name: &'static str,
+        value: &'a str,
+        original_len: usize,
+    ) -> &'a str {
+        if original_len <= budget.saturating_sub(field_name.len() + 3) {
+            return value;
+        }
+
+        // Fields past the limit are still truncated, but are left out of the marker
+        if self.fields.len() < MAX_TRUNCATED_FIELDS {
+            let header = if self.fields.is_empty() {
+                TRUNCATION_MARKER_SIZE
+            } else {
+                0
+            };
+            *budget = budget.saturating_sub(header + field_name.len() + 1 + 8);
+            self.fields.push((field_name, original_len as u64));
+        }
+
+        truncate_str(value, budget.saturating_sub(field_name.len() + 3))
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<(&'static str, u64)> {
+        self.fields
+    }
+}
+
+// Formats into a string, keeping at most `limit` bytes but counting the full formatted length.
+// This keeps a huge Debug value from growing the buffer beyond what will actually be logged.
+pub(crate) struct TruncatingWriter<'a> {
+    buf: &'a mut String,
+    limit: usize,
+    total_len: usize,
+    truncated: bool,
+}
+
+impl<'a> TruncatingWriter<'a> {
+    pub(crate) fn new(buf: &'a mut String, limit: usize) -> Self {
+        TruncatingWriter {
+            buf,
+            limit,
+            total_len: 0,
+            truncated: false,
+        }
+    }
+
+    // The length the value would have had without truncation
+    pub(crate) fn total_len(&self) -> usize {
+        self.total_len
+    }
+}
+
+impl fmt::Write for TruncatingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.total_len += s.len();
+
+        if !self.truncated {
+            let room = self.limit.saturating_sub(self.buf.len());
+            let fitted = truncate_str(s, room);
+            self.buf.push_str(fitted);
+            self.truncated = fitted.len() != s.len();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Write;
+
+    use super::*;
+
+    #[test]
+    fn truncate_on_char_boundary() {
+        assert_eq!(truncate_str("abcdef", 10), "abcdef");
+        assert_eq!(truncate_str("abcdef", 3), "abc");
+        // 'é' is two bytes, so cutting after the first byte backs up to before it
+        assert_eq!(truncate_str("aé", 2), "a");
+        assert_eq!(truncate_str("aé", 0), "");
+    }
+
+    #[test]
+    fn truncating_writer_counts_full_length() {
+        let mut buf = String::new();
+        let mut writer = TruncatingWriter::new(&mut buf, 5);
+        writer.write_str("abcd").unwrap();
+        writer.write_str("-efgh").unwrap();
+        assert_eq!(writer.total_len(), 9);
+        assert_eq!(buf, "abcd-");
+
+        let mut buf = String::new();
+        let mut writer = TruncatingWriter::new(&mut buf, 3);
+        writer.write_str("abcd").unwrap();
+        writer.write_str("e").unwrap();
+        assert_eq!(writer.total_len(), 5);
+        // Nothing is appended after the first truncated piece
+        assert_eq!(buf, "abc");
+    }
+
+    #[test]
+    fn span_fields_fit_budget() {
+        use span_values::{FieldValueIndex, SpanFieldWriter};
+
+        struct Lengths(Vec<(String, usize)>);
+        impl event_values::AddFieldAndValue for Lengths {
+            fn add_field_value(&mut self, fv: &FieldAndValue) {
+                let len = match fv.value {
+                    ValueTypes::v_str(s) => s.len(),
+                    _ => 0,
+                };
+                self.0.push((fv.field_name.to_string(), len));
+            }
+        }
+
+        let field = |field, value: ValueTypes<'static>| FieldValueIndex {
+            field,
+            value,
+            sort_index: 0,
+        };
+        let fields = [
+            field("count", ValueTypes::v_u64(1)),
+            field("first", ValueTypes::v_str(Cow::from("a".repeat(60)))),
+            field("unset", ValueTypes::None),
+            field("second", ValueTypes::v_str(Cow::from("b".repeat(60)))),
+        ];
+
+        let mut added = Lengths(Vec::new());
+        let mut writer = SpanFieldWriter::new(130);
+        writer.add_fields(&mut added, &fields);
+
+        // 130 - (6 + 8) - (6 + 62) = 48 bytes are left. The marker for the second value takes
+        // 13 + 7 + 8 = 28 of them, and 11 of the rest are for the value itself.
+        assert_eq!(
+            added.0,
+            [
+                ("count".to_string(), 0),
+                ("first".to_string(), 60),
+                ("second".to_string(), 11)
+            ]
+        );
+        assert_eq!(writer.into_truncated(), [("second", 60)]);
+    }
+    #[test]
+    fn truncation_marker_fits_budget() {
+        let mut truncated = TruncatedFields::default();
+        let mut budget = 100;
+        assert_eq!(truncated.fit(&mut budget, "small", "abc", 3), "abc");
+        assert_eq!(budget, 100);
+
+        // The marker and its first entry take 13 + 6 + 8 bytes, and the field name 5 + 3
+        let value = "x".repeat(200);
+        let fitted = truncated.fit(&mut budget, "large", &value, value.len());
+        assert_eq!(budget, 73);
+        assert_eq!(fitted.len(), 65);
+
+        // Values past the limit are still truncated, but are left out of the marker
+        let value = "x".repeat(2000);
+        let mut budget = 1000;
+        for _ in 0..200 {
+            truncated.fit(&mut budget, "f", &value, value.len());
+        }
+        assert_eq!(budget, 0);
+        assert_eq!(truncated.into_vec().len(), MAX_TRUNCATED_FIELDS);
+    }
 }
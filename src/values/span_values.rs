@@ -2,24 +2,32 @@ use std::fmt::Write;
 
 use tracing::field;
 
-use crate::values::*;
+use crate::{
+    formats::FormattedValue,
+    redaction::{hash_bytes, hash_debug, Redaction, RedactionScope},
+    values::{event_values::AddFieldAndValue, *},
+};
 
 #[doc(hidden)]
 #[derive(Default)]
 pub struct FieldValueIndex {
     pub(crate) field: &'static str,
-    pub(crate) value: ValueTypes,
+    pub(crate) value: ValueTypes<'static>,
     pub(crate) sort_index: u8,
 }
 
 // Stores the values for a span, so we can update them while the span is alive and output all the values
 // when the span ends.
+// String values are capped at max_value_len bytes so long-lived spans cannot grow without bound.
+// Redaction rules are applied before values are stored, so redacted values are never kept in memory.
 pub(crate) struct SpanValueVisitor<'a> {
     pub(crate) fields: &'a mut [FieldValueIndex],
+    pub(crate) max_value_len: usize,
+    pub(crate) redactions: RedactionScope<'a>,
 }
 
 impl SpanValueVisitor<'_> {
-    fn update_value(&mut self, field_name: &'static str, value: ValueTypes) {
+    fn update_value(&mut self, field_name: &'static str, value: ValueTypes<'static>) {
         let res = self.fields.binary_search_by_key(&field_name, |idx| {
             self.fields[idx.sort_index as usize].field
         });
@@ -29,46 +37,153 @@ impl SpanValueVisitor<'_> {
             // We don't support (and don't need to support) adding new fields that weren't in the original metadata
         }
     }
+
+    // Applies the redaction rule for the field, if there is one.
+    // Returns true if the field was redacted and should not be stored normally.
+    fn redact(&mut self, field_name: &'static str, hash: impl FnOnce() -> u64) -> bool {
+        let value = match self.redactions.get(field_name) {
+            None => return false,
+            Some(Redaction::Drop) => ValueTypes::None,
+            Some(Redaction::Mask(mask)) => ValueTypes::v_str(Cow::from(mask.clone())),
+            Some(Redaction::Hash) => ValueTypes::v_formatted(FormattedValue::Hex(hash())),
+        };
+        self.update_value(field_name, value);
+        true
+    }
+}
+
+// Adds the values stored for a span to the event logged when the span starts or stops.
+// As with the fields of an event, string values are truncated so that all the fields fit in the
+// budget, and the fields that were truncated are kept to be logged as a marker.
+// The budget is shared between calls, for spans whose fields are split across Common Schema parts.
+pub(crate) struct SpanFieldWriter {
+    budget: usize,
+    truncated: TruncatedFields,
+}
+
+impl SpanFieldWriter {
+    pub(crate) fn new(budget: usize) -> Self {
+        SpanFieldWriter {
+            budget,
+            truncated: TruncatedFields::default(),
+        }
+    }
+
+    // Fields without a value, or that the builder does not include, use none of the budget
+    pub(crate) fn add_fields<T: AddFieldAndValue>(
+        &mut self,
+        wrapped: &mut T,
+        fields: &[FieldValueIndex],
+    ) {
+        for f in fields {
+            if matches!(f.value, ValueTypes::None) || !wrapped.includes(f.field) {
+                continue;
+            }
+
+            let fitted;
+            let value = if let ValueTypes::v_str(s) = &f.value {
+                let value = self.truncated.fit(&mut self.budget, f.field, s, s.len());
+                fitted = ValueTypes::from(value);
+                &fitted
+            } else {
+                &f.value
+            };
+
+            self.budget = self
+                .budget
+                .saturating_sub(f.field.len() + 1 + value.encoded_size());
+
+            wrapped.add_field_value(&FieldAndValue {
+                field_name: f.field,
+                value,
+            });
+        }
+    }
+
+    pub(crate) fn into_truncated(self) -> Vec<(&'static str, u64)> {
+        self.truncated.into_vec()
+    }
 }
 
 impl field::Visit for SpanValueVisitor<'_> {
     fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+        if self.redact(field.name(), || hash_debug(value)) {
+            return;
+        }
+
         let mut string = String::with_capacity(10); // Just a guess
-        if write!(string, "{:?}", value).is_err() {
+        crate::formats::begin_format();
+        let mut writer = TruncatingWriter::new(&mut string, self.max_value_len);
+        if write!(writer, "{:?}", value).is_err() {
             return;
         }
+        let original_len = writer.total_len();
 
-        self.update_value(field.name(), ValueTypes::v_str(Cow::from(string)));
+        if let Some(formatted) = crate::formats::end_format(original_len) {
+            self.update_value(field.name(), ValueTypes::v_formatted(formatted));
+        } else {
+            self.update_value(field.name(), ValueTypes::v_str(Cow::from(string)));
+        }
     }
 
     fn record_f64(&mut self, field: &field::Field, value: f64) {
+        if self.redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
         self.update_value(field.name(), ValueTypes::v_f64(value));
     }
 
     fn record_i64(&mut self, field: &field::Field, value: i64) {
+        if self.redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
         self.update_value(field.name(), ValueTypes::v_i64(value));
     }
 
     fn record_u64(&mut self, field: &field::Field, value: u64) {
+        if self.redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
         self.update_value(field.name(), ValueTypes::v_u64(value));
     }
 
     fn record_i128(&mut self, field: &field::Field, value: i128) {
+        if self.redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
         self.update_value(field.name(), ValueTypes::v_i128(value));
     }
 
     fn record_u128(&mut self, field: &field::Field, value: u128) {
+        if self.redact(field.name(), || hash_bytes(&value.to_le_bytes())) {
+            return;
+        }
+
         self.update_value(field.name(), ValueTypes::v_u128(value));
     }
 
     fn record_bool(&mut self, field: &field::Field, value: bool) {
+        if self.redact(field.name(), || hash_bytes(&[value as u8])) {
+            return;
+        }
+
         self.update_value(field.name(), ValueTypes::v_bool(value));
     }
 
     fn record_str(&mut self, field: &field::Field, value: &str) {
+        if self.redact(field.name(), || hash_bytes(value.as_bytes())) {
+            return;
+        }
+
         self.update_value(
             field.name(),
-            ValueTypes::v_str(Cow::from(value.to_string())),
+            ValueTypes::v_str(Cow::from(
+                truncate_str(value, self.max_value_len).to_string(),
+            )),
         );
     }
 
@@ -0,0 +1,48 @@
+// With global_filter, the layer disables events that no session has enabled, so the
+// recorder would not see them
+#![cfg(not(feature = "global_filter"))]
+
+use std::sync::{Arc, Mutex};
+
+use tracing::{span, Level};
+use tracing_etw::{etw_event, LayerBuilder};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan};
+
+// Records the name of the span each event belongs to
+struct EventSpanRecorder(Arc<Mutex<Vec<Option<&'static str>>>>);
+
+impl<S> tracing_subscriber::Layer<S> for EventSpanRecorder
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        let span = ctx.event_span(event).map(|span| span.name());
+        self.0.lock().unwrap().push(span);
+    }
+}
+
+#[test]
+fn etw_event_explicit_parent() {
+    let recorded = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry()
+        .with(
+            LayerBuilder::new("EtwEventParentTests")
+                .__build_for_test()
+                .unwrap(),
+        )
+        .with(EventSpanRecorder(recorded.clone()));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let request = span!(Level::INFO, "request");
+        let _worker = span!(Level::INFO, "worker").entered();
+
+        etw_event!(name: "Callback", parent: &request, Level::INFO, 1, "explicit parent");
+        etw_event!(target: "callbacks", name: "Callback", parent: None, opcode: tracing_etw::Opcode::Reply, Level::INFO, 1, "no parent");
+        etw_event!(name: "Callback", Level::INFO, 1, "contextual parent");
+    });
+
+    assert_eq!(
+        *recorded.lock().unwrap(),
+        [Some("request"), None, Some("worker")]
+    );
+}
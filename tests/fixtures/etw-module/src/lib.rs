@@ -0,0 +1,9 @@
+// A shared object with its own etw_event! callsite, which the tests of tracing-etw load with dlopen
+
+use tracing::Level;
+use tracing_etw::etw_event;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn etw_module_log() {
+    etw_event!(target: "etw_module", name: "ModuleEvent", Level::INFO, 0, 0xE7E7, { loaded = true });
+}
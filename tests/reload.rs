@@ -0,0 +1,44 @@
+#![cfg(not(feature = "global_filter"))]
+
+use tracing::{callsite::DefaultCallsite, level_filters::LevelFilter, Level, Metadata};
+use tracing_etw::LayerBuilder;
+use tracing_subscriber::{filter::Targets, prelude::*};
+
+static CALLSITE: DefaultCallsite = DefaultCallsite::new(&META);
+static META: Metadata<'static> = Metadata::new(
+    "reload event",
+    "myapp::db",
+    Level::DEBUG,
+    None,
+    None,
+    None,
+    tracing::field::FieldSet::new(&[], tracing_core::identify_callsite!(&CALLSITE)),
+    tracing::metadata::Kind::EVENT,
+);
+
+// user_events has no enable callback, so a callsite that the targets enable is always
+// "sometimes" interesting, whether or not a tracepoint is enabled. With ETW, callsites
+// are "never" interesting until a session enables the provider.
+#[cfg(target_os = "linux")]
+#[test]
+fn reload_rebuilds_interest() {
+    let builder = LayerBuilder::new("ReloadTests");
+    let handle = builder.reload_handle();
+    let layer = builder
+        .build_with_targets([("myapp", LevelFilter::WARN)])
+        .unwrap();
+
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+        // Registers the callsite, and caches its interest
+        assert!(CALLSITE.interest().is_never());
+
+        handle.set_target_filter(Targets::new().with_target("myapp", Level::DEBUG));
+        assert!(CALLSITE.interest().is_sometimes());
+
+        handle.set_target_filter(Targets::new().with_target("myapp::db", Level::INFO));
+        assert!(CALLSITE.interest().is_never());
+
+        handle.clear_target_filter();
+        assert!(CALLSITE.interest().is_sometimes());
+    });
+}
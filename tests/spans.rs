@@ -1,5 +1,6 @@
 use tracing::{error_span, event, span, Level};
-use tracing_etw::LayerBuilder;
+
+use tracing_etw::{etw_span, LayerBuilder};
 use tracing_subscriber::{self, fmt::format::FmtSpan, prelude::*};
 
 #[test]
@@ -32,3 +33,38 @@ fn span_test_1() {
 
     span.record("fieldB", 12345);
 }
+
+#[test]
+fn etw_span_test() {
+    let subscriber = tracing_subscriber::registry().with(
+        LayerBuilder::new("EtwSpanTests")
+            .__build_for_test()
+            .unwrap(),
+    );
+
+    tracing::subscriber::with_default(subscriber, || {
+        let value = 5;
+        let text = "text";
+
+        let span = etw_span!(name: "Db.Query", Level::INFO, 0x10, rows = tracing::field::Empty);
+        let _one = span.enter();
+        span.record("rows", 12345);
+
+        let span2 = etw_span!(name: "Db.Connect", Level::WARN, 0x10, ?value, %text);
+        let _two = span2.enter();
+
+        let span3 = etw_span!(
+            target: "db",
+            name: "Db.Close",
+            Level::ERROR,
+            0x10,
+            3,
+            { fieldA = 7 },
+            "closing {}",
+            "connection"
+        );
+        let _three = span3.enter();
+
+        let _four = etw_span!(name: "Db.Idle", Level::INFO, 0x20).entered();
+    });
+}
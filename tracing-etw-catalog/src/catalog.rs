@@ -0,0 +1,555 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use object::{BinaryFormat, Object, ObjectSection, ObjectSegment, RelocationTarget};
+use serde_json::{json, Value};
+use tracing_etw::_details::RecordHeader;
+
+// The name of the linker section that etw_event! and etw_span! write a pointer to each
+// EventMetadata record into
+const SECTION_NAME: &str = "_etw_kw";
+
+// Records are only read from untrusted files, so the field count is sanity-checked
+// before anything is allocated for it
+const MAX_FIELDS: u64 = 1024;
+
+const LEVELS: [&str; 5] = ["ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+
+#[derive(Debug)]
+pub(crate) enum CatalogError {
+    Io(std::io::Error),
+    Parse(object::Error),
+    NotElf,
+    BadAddress(u64),
+    BadRecord(u64),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::Io(err) => write!(f, "{err}"),
+            CatalogError::Parse(err) => write!(f, "{err}"),
+            CatalogError::NotElf => write!(f, "not an ELF file"),
+            CatalogError::BadAddress(addr) => {
+                write!(f, "address {addr:#x} is not in the file")
+            }
+            CatalogError::BadRecord(addr) => {
+                write!(f, "event record at {addr:#x} is not valid")
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for CatalogError {
+    fn from(err: std::io::Error) -> Self {
+        CatalogError::Io(err)
+    }
+}
+
+impl From<object::Error> for CatalogError {
+    fn from(err: object::Error) -> Self {
+        CatalogError::Parse(err)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CatalogEvent {
+    pub(crate) name: String,
+    pub(crate) target: String,
+    pub(crate) level: &'static str,
+    pub(crate) is_span: bool,
+    pub(crate) keyword: u64,
+    // The names of the keywords defined with etw_keywords! that are set in the keyword
+    pub(crate) keyword_names: Vec<String>,
+    pub(crate) tag: u32,
+    pub(crate) opcode: u8,
+    pub(crate) version: u8,
+    pub(crate) channel: u8,
+    // 0 if the event was not given an ID with the id: argument
+    pub(crate) id: u16,
+    pub(crate) fields: Vec<String>,
+    pub(crate) file: String,
+    pub(crate) line: u32,
+}
+
+// A keyword defined with etw_keywords!
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct CatalogKeyword {
+    pub(crate) value: u64,
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Catalog {
+    pub(crate) events: Vec<CatalogEvent>,
+    pub(crate) keywords: Vec<CatalogKeyword>,
+}
+
+pub(crate) fn read_catalog(path: &Path) -> Result<Catalog, CatalogError> {
+    let data = std::fs::read(path)?;
+    parse_catalog(&data)
+}
+
+pub(crate) fn parse_catalog(data: &[u8]) -> Result<Catalog, CatalogError> {
+    let file = object::File::parse(data)?;
+    if file.format() != BinaryFormat::Elf {
+        return Err(CatalogError::NotElf);
+    }
+
+    // A binary without any etw_event! or etw_span! callsites has no section
+    let Some(section) = file.section_by_name(SECTION_NAME) else {
+        return Ok(Catalog::default());
+    };
+
+    let image = Image::new(&file)?;
+    let layout = Layout::new(image.ptr_size);
+
+    // As when the section is read at runtime, there may be null and duplicate pointers
+    let mut records = Vec::new();
+    let mut addr = section.address();
+    let end = addr + section.size();
+    while addr + image.ptr_size <= end {
+        let record = image.read_ptr(addr)?;
+        if record != 0 {
+            records.push(record);
+        }
+        addr += image.ptr_size;
+    }
+    records.sort_unstable();
+    records.dedup();
+
+    // Other versions of tracing-etw in the same binary write their records to the same section
+    let mut catalog = Catalog::default();
+    for record in records {
+        match image.record_kind(&layout, record)? {
+            Some(RecordKind::Event) => catalog.events.push(image.read_event(&layout, record)?),
+            Some(RecordKind::Keyword) => {
+                catalog.keywords.push(image.read_keyword(&layout, record)?)
+            }
+            None => {}
+        }
+    }
+
+    // The same keyword may be defined by more than one copy of the crate that defines it
+    catalog.keywords.sort_unstable();
+    catalog.keywords.dedup();
+    for event in &mut catalog.events {
+        event.keyword_names = catalog
+            .keywords
+            .iter()
+            .filter(|kw| kw.value != 0 && event.keyword & kw.value == kw.value)
+            .map(|kw| kw.name.clone())
+            .collect();
+    }
+
+    Ok(catalog)
+}
+
+enum RecordKind {
+    Event,
+    Keyword,
+}
+
+pub(crate) fn to_json(events: &[CatalogEvent]) -> Value {
+    Value::Array(
+        events
+            .iter()
+            .map(|event| {
+                json!({
+                    "name": event.name,
+                    "target": event.target,
+                    "kind": if event.is_span { "span" } else { "event" },
+                    "level": event.level,
+                    "keyword": event.keyword,
+                    "keyword_names": event.keyword_names,
+                    "tag": event.tag,
+                    "opcode": event.opcode,
+                    "version": event.version,
+                    "channel": event.channel,
+                    "id": event.id,
+                    "fields": event.fields,
+                    "file": event.file,
+                    "line": event.line,
+                })
+            })
+            .collect(),
+    )
+}
+
+// Offsets of the fields of tracing_etw::_details::EventMetadata and EventDescription,
+// which are #[repr(C)], for a target with the given pointer size
+pub(crate) struct Layout {
+    ptr: u64,
+}
+
+impl Layout {
+    pub(crate) fn new(ptr_size: u64) -> Self {
+        Layout { ptr: ptr_size }
+    }
+
+    // RecordHeader, at the start of every EventMetadata
+
+    pub(crate) fn magic(&self) -> u64 {
+        0
+    }
+
+    pub(crate) fn version(&self) -> u64 {
+        4
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        6
+    }
+
+    // EventMetadata
+
+    pub(crate) fn keyword(&self) -> u64 {
+        8
+    }
+
+    // Follows the keyword and the callsite identity, which is a fat pointer
+    pub(crate) fn event_tag(&self) -> u64 {
+        16 + 2 * self.ptr
+    }
+
+    pub(crate) fn options(&self) -> u64 {
+        self.event_tag() + 4
+    }
+
+    // Follows the opcode, version, and channel bytes of the options, and a padding byte
+    pub(crate) fn event_id(&self) -> u64 {
+        self.options() + 4
+    }
+
+    // Follows the options, aligned to a pointer
+    pub(crate) fn description(&self) -> u64 {
+        (self.event_id() + 2).next_multiple_of(self.ptr)
+    }
+
+    // Ends with the description, which ends with the level and is_span bytes
+    pub(crate) fn record_size(&self) -> u64 {
+        (self.description() + self.is_span() + 1).next_multiple_of(self.ptr)
+    }
+
+    // KeywordDefinition
+
+    pub(crate) fn keyword_value(&self) -> u64 {
+        8
+    }
+
+    pub(crate) fn keyword_name(&self) -> u64 {
+        16
+    }
+
+    pub(crate) fn keyword_record_size(&self) -> u64 {
+        self.keyword_name() + self.str_size()
+    }
+
+    // EventDescription, relative to the start of the description
+
+    pub(crate) fn name(&self) -> u64 {
+        0
+    }
+
+    pub(crate) fn target(&self) -> u64 {
+        2 * self.ptr
+    }
+
+    pub(crate) fn file(&self) -> u64 {
+        4 * self.ptr
+    }
+
+    pub(crate) fn fields(&self) -> u64 {
+        6 * self.ptr
+    }
+
+    pub(crate) fn field_count(&self) -> u64 {
+        7 * self.ptr
+    }
+
+    pub(crate) fn line(&self) -> u64 {
+        8 * self.ptr
+    }
+
+    pub(crate) fn level(&self) -> u64 {
+        self.line() + 4
+    }
+
+    pub(crate) fn is_span(&self) -> u64 {
+        self.level() + 1
+    }
+
+    // The size of a StaticStr
+    pub(crate) fn str_size(&self) -> u64 {
+        2 * self.ptr
+    }
+}
+
+// The loaded segments of an ELF file, and the relocations that the dynamic loader
+// would apply to them
+struct Image<'d> {
+    segments: Vec<(u64, &'d [u8])>,
+    // Relocations that do not refer to a symbol, such as R_X86_64_RELATIVE, by address.
+    // The load address of the file is taken to be 0.
+    relocations: HashMap<u64, (i64, bool)>,
+    ptr_size: u64,
+    little_endian: bool,
+}
+
+impl<'d> Image<'d> {
+    fn new(file: &object::File<'d>) -> Result<Self, CatalogError> {
+        let mut segments = Vec::new();
+        for segment in file.segments() {
+            segments.push((segment.address(), segment.data()?));
+        }
+
+        // Packed (RELR) relocations are not listed here; they store the address in the file
+        // itself, so the value that is already there is correct.
+        let mut relocations = HashMap::new();
+        if let Some(dynamic_relocations) = file.dynamic_relocations() {
+            for (addr, relocation) in dynamic_relocations {
+                if relocation.target() == RelocationTarget::Absolute {
+                    relocations.insert(
+                        addr,
+                        (relocation.addend(), relocation.has_implicit_addend()),
+                    );
+                }
+            }
+        }
+
+        Ok(Image {
+            segments,
+            relocations,
+            ptr_size: if file.is_64() { 8 } else { 4 },
+            little_endian: file.is_little_endian(),
+        })
+    }
+
+    fn bytes(&self, addr: u64, len: u64) -> Result<&'d [u8], CatalogError> {
+        self.segments
+            .iter()
+            .find_map(|(start, data)| {
+                let offset = usize::try_from(addr.checked_sub(*start)?).ok()?;
+                let len = usize::try_from(len).ok()?;
+                data.get(offset..offset.checked_add(len)?)
+            })
+            .ok_or(CatalogError::BadAddress(addr))
+    }
+
+    fn read_uint(&self, addr: u64, size: u64) -> Result<u64, CatalogError> {
+        let bytes = self.bytes(addr, size)?;
+        let mut value = [0u8; 8];
+        if self.little_endian {
+            value[..bytes.len()].copy_from_slice(bytes);
+            Ok(u64::from_le_bytes(value))
+        } else {
+            value[8 - bytes.len()..].copy_from_slice(bytes);
+            Ok(u64::from_be_bytes(value))
+        }
+    }
+
+    fn read_u8(&self, addr: u64) -> Result<u8, CatalogError> {
+        Ok(self.bytes(addr, 1)?[0])
+    }
+
+    fn read_usize(&self, addr: u64) -> Result<u64, CatalogError> {
+        self.read_uint(addr, self.ptr_size)
+    }
+
+    fn read_ptr(&self, addr: u64) -> Result<u64, CatalogError> {
+        let value = self.read_usize(addr)?;
+        Ok(match self.relocations.get(&addr) {
+            Some(&(addend, true)) => value.wrapping_add(addend as u64),
+            Some(&(addend, false)) => addend as u64,
+            None => value,
+        })
+    }
+
+    // Reads a StaticStr
+    fn read_str(&self, addr: u64) -> Result<String, CatalogError> {
+        let len = self.read_usize(addr + self.ptr_size)?;
+        if len == 0 {
+            return Ok(String::new());
+        }
+
+        let ptr = self.read_ptr(addr)?;
+        let bytes = self.bytes(ptr, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| CatalogError::BadRecord(addr))
+    }
+
+    // Records from versions of tracing-etw from before the header was added start with the
+    // 8 byte keyword, so the header can be read from any record.
+    // Records with a layout other than the one this tool was built with are skipped.
+    fn record_kind(
+        &self,
+        layout: &Layout,
+        record: u64,
+    ) -> Result<Option<RecordKind>, CatalogError> {
+        let magic = self.read_uint(record + layout.magic(), 4)?;
+        let version = self.read_uint(record + layout.version(), 2)?;
+        let size = self.read_uint(record + layout.size(), 2)?;
+        if version != u64::from(RecordHeader::VERSION) {
+            return Ok(None);
+        }
+
+        Ok(
+            if magic == u64::from(RecordHeader::MAGIC) && size == layout.record_size() {
+                Some(RecordKind::Event)
+            } else if magic == u64::from(RecordHeader::KEYWORD_MAGIC)
+                && size == layout.keyword_record_size()
+            {
+                Some(RecordKind::Keyword)
+            } else {
+                None
+            },
+        )
+    }
+
+    fn read_keyword(&self, layout: &Layout, record: u64) -> Result<CatalogKeyword, CatalogError> {
+        Ok(CatalogKeyword {
+            value: self.read_uint(record + layout.keyword_value(), 8)?,
+            name: self.read_str(record + layout.keyword_name())?,
+        })
+    }
+
+    fn read_event(&self, layout: &Layout, record: u64) -> Result<CatalogEvent, CatalogError> {
+        let options = record + layout.options();
+        let desc = record + layout.description();
+
+        let level = self.read_u8(desc + layout.level())?;
+        let level = level
+            .checked_sub(1)
+            .and_then(|level| LEVELS.get(level as usize))
+            .ok_or(CatalogError::BadRecord(record))?;
+
+        let field_count = self.read_usize(desc + layout.field_count())?;
+        if field_count > MAX_FIELDS {
+            return Err(CatalogError::BadRecord(record));
+        }
+        let fields_ptr = self.read_ptr(desc + layout.fields())?;
+        let fields = (0..field_count)
+            .map(|i| self.read_str(fields_ptr + i * layout.str_size()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(CatalogEvent {
+            name: self.read_str(desc + layout.name())?,
+            target: self.read_str(desc + layout.target())?,
+            level,
+            is_span: self.read_u8(desc + layout.is_span())? != 0,
+            keyword: self.read_uint(record + layout.keyword(), 8)?,
+            keyword_names: Vec::new(),
+            tag: self.read_uint(record + layout.event_tag(), 4)? as u32,
+            opcode: self.read_u8(options)?,
+            version: self.read_u8(options + 1)?,
+            channel: self.read_u8(options + 2)?,
+            id: self.read_uint(record + layout.event_id(), 2)? as u16,
+            fields,
+            file: self.read_str(desc + layout.file())?,
+            line: self.read_uint(desc + layout.line(), 4)? as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem::{offset_of, size_of};
+
+    use tracing_etw::_details::{
+        EventDescription, EventMetadata, EventOptions, KeywordDefinition, RecordHeader, StaticStr,
+    };
+
+    use super::*;
+
+    #[test]
+    fn layout_matches() {
+        let layout = Layout::new(size_of::<usize>() as u64);
+        let description = offset_of!(EventMetadata, description) as u64;
+
+        assert_eq!(layout.magic(), offset_of!(RecordHeader, magic) as u64);
+        assert_eq!(layout.version(), offset_of!(RecordHeader, version) as u64);
+        assert_eq!(layout.size(), offset_of!(RecordHeader, size) as u64);
+        assert_eq!(layout.keyword(), offset_of!(EventMetadata, kw) as u64);
+        assert_eq!(
+            layout.event_tag(),
+            offset_of!(EventMetadata, event_tag) as u64
+        );
+        assert_eq!(layout.options(), offset_of!(EventMetadata, options) as u64);
+        assert_eq!(
+            layout.event_id(),
+            layout.options() + offset_of!(EventOptions, id) as u64
+        );
+        assert_eq!(layout.description(), description);
+        assert_eq!(layout.target(), offset_of!(EventDescription, target) as u64);
+        assert_eq!(layout.file(), offset_of!(EventDescription, file) as u64);
+        assert_eq!(layout.fields(), offset_of!(EventDescription, fields) as u64);
+        assert_eq!(
+            layout.field_count(),
+            offset_of!(EventDescription, field_count) as u64
+        );
+        assert_eq!(layout.line(), offset_of!(EventDescription, line) as u64);
+        assert_eq!(layout.level(), offset_of!(EventDescription, level) as u64);
+        assert_eq!(
+            layout.is_span(),
+            offset_of!(EventDescription, is_span) as u64
+        );
+        assert_eq!(layout.str_size(), size_of::<StaticStr>() as u64);
+        assert_eq!(layout.record_size(), size_of::<EventMetadata>() as u64);
+        assert_eq!(
+            layout.keyword_value(),
+            offset_of!(KeywordDefinition, value) as u64
+        );
+        assert_eq!(
+            layout.keyword_name(),
+            offset_of!(KeywordDefinition, name) as u64
+        );
+        assert_eq!(
+            layout.keyword_record_size(),
+            size_of::<KeywordDefinition>() as u64
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn catalog_of_test_binary() {
+        tracing_etw::etw_keywords! {
+            CliStorage = 0x20,
+        }
+        tracing_etw::etw_event!(target: "catalog_test", name: "Catalog.Cli", id: 300, tracing::Level::WARN, CliStorage, 7, { id = 1, size = 2 });
+
+        // A record from a version of tracing-etw without a header, which is skipped
+        static FOREIGN: [u64; 8] = [0x8000, 1, 2, 3, 4, 5, 6, 7];
+        #[unsafe(link_section = "_etw_kw")]
+        #[used]
+        static mut FOREIGN_PTR: *const [u64; 8] = &FOREIGN;
+
+        let catalog = read_catalog(&std::env::current_exe().unwrap()).unwrap();
+
+        // tracing-etw may be built with runtime_metadata through feature unification,
+        // e.g. with `cargo test --workspace --features runtime_metadata`. Its records are
+        // then never written to the section, and only the foreign record is found.
+        if tracing_etw::_details::RUNTIME_METADATA {
+            assert!(catalog.events.is_empty());
+            return;
+        }
+
+        let event = catalog
+            .events
+            .iter()
+            .find(|event| event.name == "Catalog.Cli")
+            .unwrap();
+
+        assert_eq!(event.target, "catalog_test");
+        assert_eq!(event.level, "WARN");
+        assert!(!event.is_span);
+        assert_eq!(event.keyword, 0x20);
+        assert_eq!(event.keyword_names, ["CliStorage"]);
+        assert!(catalog.keywords.contains(&CatalogKeyword {
+            value: 0x20,
+            name: "CliStorage".to_string()
+        }));
+        assert_eq!(event.tag, 7);
+        assert_eq!(event.channel, 11);
+        assert_eq!(event.id, 300);
+        assert_eq!(event.fields, ["id", "size"]);
+        assert_eq!(event.file, file!());
+    }
+}
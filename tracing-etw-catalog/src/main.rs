@@ -0,0 +1,88 @@
+//! Prints a JSON catalog of the `etw_event!` and `etw_span!` callsites compiled into an ELF
+//! executable or shared library, without running it.
+//!
+//! ```text
+//! tracing-etw-catalog [--manifest <provider name> [--hashed-ids]] <path to binary>
+//! ```
+//!
+//! Each callsite is written with its name, target, level, keyword, tag, opcode, version,
+//! channel, ID, field names, and source location. The names of the keywords defined with
+//! `etw_keywords!` that the keyword sets are also written, and are used as the names of
+//! the keywords in manifests. Events logged with the `tracing` macros
+//! (e.g. `event!`) have no entry in the catalog.
+//!
+//! With `--manifest`, an ETW instrumentation manifest for the events is printed instead,
+//! for consumers that need a manifest to decode events. The provider GUID is generated
+//! from the provider name in the same way as `LayerBuilder::new`. Events given an ID with
+//! `id:` keep it. With `--hashed-ids`, the others get the same IDs as they do with
+//! `LayerBuilder::with_hashed_event_ids`; without it, they are logged with ID 0 and are left
+//! out of the manifest. It is an error for events with different names to have the same ID.
+//! All fields are declared as strings, since field types are not known until an event is
+//! logged.
+//!
+//! Each record starts with a header that identifies its layout. Records written by versions
+//! of tracing-etw with a different layout than this tool's are skipped.
+//!
+//! Binaries built with tracing-etw's `runtime_metadata` feature register their callsites
+//! when they are first run, rather than writing them to a linker section, so their catalog
+//! is empty. This includes binaries where the feature is enabled by another crate in the
+//! same build.
+
+use std::{path::PathBuf, process::ExitCode};
+
+mod catalog;
+mod manifest;
+
+const USAGE: &str =
+    "usage: tracing-etw-catalog [--manifest <provider name> [--hashed-ids]] <binary>";
+
+fn main() -> ExitCode {
+    let mut args = std::env::args_os().skip(1).peekable();
+    let mut provider_name = None;
+    if args.peek().is_some_and(|arg| arg == "--manifest") {
+        args.next();
+        let Some(name) = args.next().and_then(|name| name.into_string().ok()) else {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        };
+        provider_name = Some(name);
+    }
+    let mut hashed_ids = false;
+    if provider_name.is_some() && args.peek().is_some_and(|arg| arg == "--hashed-ids") {
+        args.next();
+        hashed_ids = true;
+    }
+    let (Some(path), None) = (args.next(), args.next()) else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let path = PathBuf::from(path);
+
+    match catalog::read_catalog(&path) {
+        Ok(catalog) => {
+            if let Some(provider_name) = provider_name {
+                let binary_name = path
+                    .file_name()
+                    .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                match manifest::to_manifest(&provider_name, &binary_name, &catalog, hashed_ids) {
+                    Ok(xml) => print!("{xml}"),
+                    Err(err) => {
+                        eprintln!("tracing-etw-catalog: {}: {err}", path.display());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            } else {
+                let json = catalog::to_json(&catalog.events);
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&json).expect("catalog is valid JSON")
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("tracing-etw-catalog: {}: {err}", path.display());
+            ExitCode::FAILURE
+        }
+    }
+}
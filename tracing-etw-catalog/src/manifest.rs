@@ -0,0 +1,482 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    fmt::{self, Write},
+};
+
+use tracing_etw::{_details::hashed_event_id, native::GuidWrapper};
+
+use crate::catalog::{Catalog, CatalogEvent};
+
+// Opcodes that are predefined by Windows, and do not need to be declared by the provider
+const WINDOWS_OPCODES: [(u8, &str); 11] = [
+    (0, "win:Info"),
+    (1, "win:Start"),
+    (2, "win:Stop"),
+    (3, "win:DC_Start"),
+    (4, "win:DC_Stop"),
+    (5, "win:Extension"),
+    (6, "win:Reply"),
+    (7, "win:Resume"),
+    (8, "win:Suspend"),
+    (9, "win:Send"),
+    (240, "win:Receive"),
+];
+
+// Generates an ETW instrumentation manifest for the events in the catalog, logged by the
+// provider with the given name from the binary with the given file name.
+//
+// - The provider GUID is generated from the provider name, as LayerBuilder does by default.
+// - Each bit used by a keyword is declared as its own keyword, named after the keyword defined
+//   with etw_keywords! for that bit, if there is one.
+// - Each event name is declared as a task.
+// - Events keep the ID given with `id:`. With `hashed_ids`, the others get the ID that
+//   LayerBuilder::with_hashed_event_ids gives them. Events without an ID are logged with ID 0,
+//   so a manifest cannot describe them, and they are left out.
+// - Callsites with the same name and ID are declared once, with the fields of the first one.
+//   Events with different names and the same ID are an error, as the manifest would be invalid.
+// - Field types are not known until the event is logged, so every field other than the
+//   timestamp that this crate adds is declared as a UTF-8 string.
+//
+// Spans are not included.
+pub(crate) fn to_manifest(
+    provider_name: &str,
+    binary_name: &str,
+    catalog: &Catalog,
+    hashed_ids: bool,
+) -> Result<String, ManifestError> {
+    let mut events: Vec<&CatalogEvent> = catalog
+        .events
+        .iter()
+        .filter(|event| !event.is_span)
+        .collect();
+    events.sort_by(|a, b| (&a.name, &a.file, a.line).cmp(&(&b.name, &b.file, b.line)));
+
+    let (events, values) = event_values(&events, provider_name, hashed_ids)?;
+
+    let keyword_mask = events.iter().fold(0u64, |mask, event| mask | event.keyword);
+    let keyword_names = KeywordNames::new(catalog);
+
+    // Tasks are numbered in order of name
+    let names: BTreeSet<&str> = events.iter().map(|event| event.name.as_str()).collect();
+    let tasks: BTreeMap<&str, usize> = names.into_iter().zip(1..).collect();
+
+    let mut opcodes: Vec<u8> = events
+        .iter()
+        .map(|event| event.opcode)
+        .filter(|opcode| windows_opcode(*opcode).is_none())
+        .collect();
+    opcodes.sort_unstable();
+    opcodes.dedup();
+
+    let guid = u128::from(&GuidWrapper::from_name(provider_name));
+    let guid = format!("{guid:032x}");
+
+    let mut xml = String::new();
+    // Writing to a String cannot fail
+    let _ = write_manifest(
+        &mut xml,
+        provider_name,
+        &guid,
+        binary_name,
+        keyword_mask,
+        &keyword_names,
+        &tasks,
+        &opcodes,
+        &events,
+        &values,
+    );
+    Ok(xml)
+}
+
+#[derive(Debug)]
+pub(crate) enum ManifestError {
+    DuplicateId(u16, String, String),
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::DuplicateId(id, first, second) => {
+                write!(f, "events {first:?} and {second:?} both have ID {id}")
+            }
+        }
+    }
+}
+
+// The events to declare, sorted by ID, with their IDs
+fn event_values<'a>(
+    events: &[&'a CatalogEvent],
+    provider_name: &str,
+    hashed_ids: bool,
+) -> Result<(Vec<&'a CatalogEvent>, Vec<u16>), ManifestError> {
+    let mut by_id: BTreeMap<u16, &CatalogEvent> = BTreeMap::new();
+    for event in events {
+        let id = match event.id {
+            0 if hashed_ids => hashed_event_id(provider_name, &event.name),
+            id => id,
+        };
+        if id == 0 {
+            continue;
+        }
+        match by_id.entry(id) {
+            Entry::Vacant(entry) => {
+                entry.insert(event);
+            }
+            Entry::Occupied(entry) if entry.get().name == event.name => {}
+            Entry::Occupied(entry) => {
+                return Err(ManifestError::DuplicateId(
+                    id,
+                    entry.get().name.clone(),
+                    event.name.clone(),
+                ));
+            }
+        }
+    }
+    Ok((
+        by_id.values().copied().collect(),
+        by_id.into_keys().collect(),
+    ))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_manifest(
+    xml: &mut String,
+    provider_name: &str,
+    guid: &str,
+    binary_name: &str,
+    keyword_mask: u64,
+    keyword_names: &KeywordNames,
+    tasks: &BTreeMap<&str, usize>,
+    opcodes: &[u8],
+    events: &[&CatalogEvent],
+    values: &[u16],
+) -> std::fmt::Result {
+    writeln!(xml, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(
+        xml,
+        r#"<instrumentationManifest xmlns="http://schemas.microsoft.com/win/2004/08/events" xmlns:win="http://manifests.microsoft.com/win/2004/08/windows/events" xmlns:xs="http://www.w3.org/2001/XMLSchema">"#
+    )?;
+    writeln!(xml, "  <instrumentation>")?;
+    writeln!(xml, "    <events>")?;
+    writeln!(
+        xml,
+        r#"      <provider name="{}" guid="{{{}-{}-{}-{}-{}}}" symbol="{}" resourceFileName="{}" messageFileName="{}">"#,
+        escape(provider_name),
+        &guid[0..8],
+        &guid[8..12],
+        &guid[12..16],
+        &guid[16..20],
+        &guid[20..32],
+        symbol(provider_name),
+        escape(binary_name),
+        escape(binary_name),
+    )?;
+
+    if keyword_mask != 0 {
+        writeln!(xml, "        <keywords>")?;
+        for bit in (0..64).filter(|bit| keyword_mask & (1 << bit) != 0) {
+            writeln!(
+                xml,
+                r#"          <keyword name="{}" mask="0x{:x}"/>"#,
+                escape(&keyword_names.get(bit)),
+                1u64 << bit
+            )?;
+        }
+        writeln!(xml, "        </keywords>")?;
+    }
+
+    if !tasks.is_empty() {
+        writeln!(xml, "        <tasks>")?;
+        for (name, value) in tasks {
+            writeln!(
+                xml,
+                r#"          <task name="{}" symbol="{}" value="{value}"/>"#,
+                escape(name),
+                symbol(name)
+            )?;
+        }
+        writeln!(xml, "        </tasks>")?;
+    }
+
+    if !opcodes.is_empty() {
+        writeln!(xml, "        <opcodes>")?;
+        for opcode in opcodes {
+            writeln!(
+                xml,
+                r#"          <opcode name="{}" value="{opcode}"/>"#,
+                opcode_name(*opcode)
+            )?;
+        }
+        writeln!(xml, "        </opcodes>")?;
+    }
+
+    if !events.is_empty() {
+        writeln!(xml, "        <templates>")?;
+        for (event, value) in events.iter().zip(values) {
+            writeln!(xml, r#"          <template tid="T{value}">"#)?;
+            writeln!(
+                xml,
+                r#"            <data name="time" inType="win:SYSTEMTIME" outType="xs:dateTime"/>"#
+            )?;
+            for field in &event.fields {
+                writeln!(
+                    xml,
+                    r#"            <data name="{}" inType="win:AnsiString" outType="win:Utf8"/>"#,
+                    escape(field)
+                )?;
+            }
+            writeln!(xml, "          </template>")?;
+        }
+        writeln!(xml, "        </templates>")?;
+
+        writeln!(xml, "        <events>")?;
+        for (event, value) in events.iter().zip(values) {
+            write!(
+                xml,
+                r#"          <event value="{value}" version="{}" level="{}" task="{}" opcode="{}" template="T{value}""#,
+                event.version,
+                level_name(event.level),
+                escape(&event.name),
+                opcode_name(event.opcode),
+            )?;
+            if event.keyword != 0 {
+                let keywords: Vec<String> = (0..64)
+                    .filter(|bit| event.keyword & (1 << bit) != 0)
+                    .map(|bit| escape(&keyword_names.get(bit)))
+                    .collect();
+                write!(xml, r#" keywords="{}""#, keywords.join(" "))?;
+            }
+            writeln!(xml, "/>")?;
+        }
+        writeln!(xml, "        </events>")?;
+    }
+
+    writeln!(xml, "      </provider>")?;
+    writeln!(xml, "    </events>")?;
+    writeln!(xml, "  </instrumentation>")?;
+    writeln!(xml, "</instrumentationManifest>")
+}
+
+fn windows_opcode(opcode: u8) -> Option<&'static str> {
+    WINDOWS_OPCODES
+        .iter()
+        .find(|(value, _)| *value == opcode)
+        .map(|(_, name)| *name)
+}
+
+fn opcode_name(opcode: u8) -> String {
+    windows_opcode(opcode).map_or_else(|| format!("Opcode{opcode}"), str::to_string)
+}
+
+// TRACE events are logged with a level one higher than win:Verbose, but levels above
+// win:Verbose that are not declared by the provider are not allowed in a manifest.
+fn level_name(level: &str) -> &'static str {
+    match level {
+        "ERROR" => "win:Error",
+        "WARN" => "win:Warning",
+        "INFO" => "win:Informational",
+        _ => "win:Verbose",
+    }
+}
+
+// The name of each keyword bit. Keywords defined with etw_keywords! are only used for the
+// name of a bit if they set just that bit.
+struct KeywordNames<'a> {
+    names: BTreeMap<u32, &'a str>,
+}
+
+impl<'a> KeywordNames<'a> {
+    fn new(catalog: &'a Catalog) -> Self {
+        let mut names = BTreeMap::new();
+        // The keywords are sorted, so the first name for a bit is used
+        for keyword in &catalog.keywords {
+            if keyword.value.count_ones() == 1 {
+                names
+                    .entry(keyword.value.trailing_zeros())
+                    .or_insert(keyword.name.as_str());
+            }
+        }
+        KeywordNames { names }
+    }
+
+    fn get(&self, bit: u32) -> String {
+        self.names.get(&bit).map_or_else(
+            || format!("Keyword_0x{:x}", 1u64 << bit),
+            |name| name.to_string(),
+        )
+    }
+}
+
+// A C identifier for the name, for the header that the manifest compiler generates
+fn symbol(name: &str) -> String {
+    let mut symbol: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !symbol.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        symbol.insert(0, '_');
+    }
+    symbol
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::catalog::CatalogKeyword;
+
+    fn event(
+        name: &str,
+        level: &'static str,
+        keyword: u64,
+        opcode: u8,
+        fields: &[&str],
+    ) -> CatalogEvent {
+        CatalogEvent {
+            name: name.to_string(),
+            target: "manifest_test".to_string(),
+            level,
+            is_span: false,
+            keyword,
+            keyword_names: Vec::new(),
+            tag: 0,
+            opcode,
+            version: 1,
+            channel: 11,
+            id: 0,
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            file: "src/main.rs".to_string(),
+            line: 1,
+        }
+    }
+
+    #[test]
+    fn manifest() {
+        let catalog = Catalog {
+            events: vec![
+                event("Db.Query", "INFO", 0x11, 0, &["table", "rows"]),
+                event("Request<Send>", "TRACE", 0x10, 9, &["message"]),
+                event("Custom", "WARN", 0, 20, &[]),
+                CatalogEvent {
+                    id: 2,
+                    ..event("Auth.Login", "INFO", 0, 0, &["user"])
+                },
+            ],
+            keywords: vec![
+                CatalogKeyword {
+                    value: 0x10,
+                    name: "Network".to_string(),
+                },
+                CatalogKeyword {
+                    value: 0x11,
+                    name: "Everything".to_string(),
+                },
+            ],
+        };
+
+        let xml = to_manifest("My.Provider", "app", &catalog, true).unwrap();
+
+        let guid = format!(
+            "{:032x}",
+            u128::from(&GuidWrapper::from_name("My.Provider"))
+        );
+        assert!(xml.contains(&format!(
+            r#"<provider name="My.Provider" guid="{{{}-{}-{}-{}-{}}}" symbol="My_Provider""#,
+            &guid[0..8],
+            &guid[8..12],
+            &guid[12..16],
+            &guid[16..20],
+            &guid[20..32]
+        )));
+        assert!(xml.contains(r#"<keyword name="Keyword_0x1" mask="0x1"/>"#));
+        assert!(xml.contains(r#"<keyword name="Network" mask="0x10"/>"#));
+        assert!(
+            xml.contains(r#"<task name="Request&lt;Send&gt;" symbol="Request_Send_" value="4"/>"#)
+        );
+        assert!(xml.contains(r#"<opcode name="Opcode20" value="20"/>"#));
+        assert!(xml.contains(r#"<data name="rows" inType="win:AnsiString" outType="win:Utf8"/>"#));
+
+        // Events without an explicit ID get the ID that the layer gives them
+        let query = hashed_event_id("My.Provider", "Db.Query");
+        let request = hashed_event_id("My.Provider", "Request<Send>");
+        let custom = hashed_event_id("My.Provider", "Custom");
+        assert!(xml.contains(&format!(
+            r#"<event value="{query}" version="1" level="win:Informational" task="Db.Query" opcode="win:Info" template="T{query}" keywords="Keyword_0x1 Network"/>"#
+        )));
+        assert!(xml.contains(&format!(
+            r#"<event value="{request}" version="1" level="win:Verbose" task="Request&lt;Send&gt;" opcode="win:Send" template="T{request}" keywords="Network"/>"#
+        )));
+        assert!(xml.contains(&format!(
+            r#"<event value="{custom}" version="1" level="win:Warning" task="Custom" opcode="Opcode20" template="T{custom}"/>"#
+        )));
+        // Events with an explicit ID keep it
+        assert!(xml.contains(r#"<template tid="T2">"#));
+        assert!(xml.contains(
+            r#"<event value="2" version="1" level="win:Informational" task="Auth.Login" opcode="win:Info" template="T2"/>"#
+        ));
+
+        // Without hashed IDs, the events without an explicit ID are logged with ID 0,
+        // and are left out
+        let xml = to_manifest("My.Provider", "app", &catalog, false).unwrap();
+        assert!(xml.contains(r#"task="Auth.Login""#));
+        assert!(!xml.contains(r#"task="Db.Query""#));
+        assert!(!xml.contains(r#"<task name="Db.Query""#));
+        assert_eq!(xml.matches("<event value=").count(), 1);
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let catalog = Catalog {
+            events: vec![
+                CatalogEvent {
+                    id: 5,
+                    ..event("Db.Query", "INFO", 0, 0, &["table"])
+                },
+                CatalogEvent {
+                    id: 5,
+                    line: 2,
+                    ..event("Db.Query", "INFO", 0, 0, &["table"])
+                },
+            ],
+            keywords: Vec::new(),
+        };
+
+        // Callsites with the same name and ID are declared once
+        let xml = to_manifest("My.Provider", "app", &catalog, false).unwrap();
+        assert_eq!(xml.matches("<event value=").count(), 1);
+
+        let catalog = Catalog {
+            events: vec![
+                CatalogEvent {
+                    id: 5,
+                    ..event("Db.Query", "INFO", 0, 0, &["table"])
+                },
+                CatalogEvent {
+                    id: 5,
+                    ..event("Request", "INFO", 0, 0, &[])
+                },
+            ],
+            keywords: Vec::new(),
+        };
+
+        let err = to_manifest("My.Provider", "app", &catalog, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"events "Db.Query" and "Request" both have ID 5"#
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Attribute macros for [tracing-etw](https://docs.rs/tracing-etw).
+//!
+//! These macros are re-exported by `tracing-etw` when its `attributes` feature is enabled,
+//! and should normally be used from there rather than by depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned, ToTokens};
+use syn::{
+    parse_macro_input, spanned::Spanned, visit_mut::VisitMut, Expr, FnArg, Ident, ItemFn, LitStr,
+    Pat, ReturnType, Type,
+};
+
+/// Instruments a function to create and enter an ETW/user_events span with a keyword,
+/// tag, and name every time the function is called.
+///
+/// This is equivalent to [`#[tracing::instrument]`](https://docs.rs/tracing/latest/tracing/attr.instrument.html),
+/// but the span is created with `tracing_etw::etw_span!`, so its keyword and tag are
+/// registered in the same static metadata as `etw_event!` events.
+///
+/// Arguments:
+/// - `keyword = <expr>`: the keyword for the span. Required.
+/// - `tag = <expr>`: the event tag for the span. Defaults to `0`.
+/// - `name = "<name>"`: the name of the span. Defaults to the name of the function.
+/// - `level = "<level>"`: one of `"trace"`, `"debug"`, `"info"`, `"warn"`, or `"error"`,
+///   or a `tracing::Level` expression. Defaults to `"info"`.
+/// - `target = "<target>"`: the target of the span. Defaults to the module path.
+/// - `skip(<arg>, ...)` or `skip_all`: function arguments that are not recorded.
+///   All other arguments that are bound to a plain identifier are recorded with their
+///   [Debug][std::fmt::Debug] implementation. `self` is never recorded.
+/// - `fields(<field>, ...)`: additional fields for the span, using the same syntax as `span!`.
+///
+/// The crate using this macro must also depend on `tracing` and `tracing-core`.
+///
+/// ```ignore
+/// use tracing_etw::etw_instrument;
+///
+/// #[etw_instrument(keyword = 0x10, tag = 3, name = "Db.Query", skip(conn))]
+/// fn query(conn: &Connection, table: &str) -> usize {
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn etw_instrument(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut attr = InstrumentArgs::default();
+    let parser = syn::meta::parser(|meta| attr.parse(meta));
+    parse_macro_input!(args with parser);
+
+    let item = parse_macro_input!(item as ItemFn);
+
+    match expand(attr, item) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct InstrumentArgs {
+    keyword: Option<Expr>,
+    tag: Option<Expr>,
+    name: Option<LitStr>,
+    level: Option<TokenStream2>,
+    target: Option<LitStr>,
+    skip: Vec<Ident>,
+    skip_all: bool,
+    fields: Option<TokenStream2>,
+}
+
+impl InstrumentArgs {
+    fn parse(&mut self, meta: syn::meta::ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("keyword") {
+            self.keyword = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("tag") {
+            self.tag = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("name") {
+            self.name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("target") {
+            self.target = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("level") {
+            let value: Expr = meta.value()?.parse()?;
+            self.level = Some(parse_level(value)?);
+        } else if meta.path.is_ident("skip_all") {
+            self.skip_all = true;
+        } else if meta.path.is_ident("skip") {
+            meta.parse_nested_meta(|arg| {
+                self.skip.push(arg.path.require_ident()?.clone());
+                Ok(())
+            })?;
+        } else if meta.path.is_ident("fields") {
+            let content;
+            syn::parenthesized!(content in meta.input);
+            self.fields = Some(content.parse()?);
+        } else {
+            return Err(meta.error("unsupported etw_instrument argument"));
+        }
+        Ok(())
+    }
+}
+
+fn parse_level(value: Expr) -> syn::Result<TokenStream2> {
+    let Expr::Lit(syn::ExprLit {
+        lit: syn::Lit::Str(level),
+        ..
+    }) = &value
+    else {
+        return Ok(value.into_token_stream());
+    };
+
+    let level_ident = match level.value().to_ascii_lowercase().as_str() {
+        "trace" => "TRACE",
+        "debug" => "DEBUG",
+        "info" => "INFO",
+        "warn" => "WARN",
+        "error" => "ERROR",
+        _ => {
+            return Err(syn::Error::new(
+                level.span(),
+                "expected one of \"trace\", \"debug\", \"info\", \"warn\", or \"error\"",
+            ))
+        }
+    };
+    let level_ident = Ident::new(level_ident, level.span());
+    Ok(quote!(tracing::Level::#level_ident))
+}
+
+fn expand(args: InstrumentArgs, item: ItemFn) -> syn::Result<TokenStream2> {
+    let Some(keyword) = args.keyword else {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "etw_instrument requires a keyword, e.g. #[etw_instrument(keyword = 0x10)]",
+        ));
+    };
+
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = item;
+
+    let name = match args.name {
+        Some(name) => name,
+        None => LitStr::new(&sig.ident.to_string(), sig.ident.span()),
+    };
+    let target = match args.target {
+        Some(target) => target.into_token_stream(),
+        None => quote!(module_path!()),
+    };
+    let level = args.level.unwrap_or_else(|| quote!(tracing::Level::INFO));
+    let tag = args
+        .tag
+        .map_or_else(|| quote!(0), |tag| tag.into_token_stream());
+
+    for skipped in &args.skip {
+        let is_arg = sig.inputs.iter().any(|input| match input {
+            FnArg::Typed(arg) => matches!(&*arg.pat, Pat::Ident(pat) if pat.ident == *skipped),
+            FnArg::Receiver(_) => false,
+        });
+        if !is_arg {
+            return Err(syn::Error::new(
+                skipped.span(),
+                "skipped argument is not a parameter of this function",
+            ));
+        }
+    }
+
+    let mut fields = Vec::new();
+    if !args.skip_all {
+        for input in &sig.inputs {
+            if let FnArg::Typed(arg) = input {
+                if let Pat::Ident(pat) = &*arg.pat {
+                    let ident = &pat.ident;
+                    if !args.skip.contains(ident) {
+                        fields.push(quote_spanned!(ident.span()=> #ident = ?#ident));
+                    }
+                }
+            }
+        }
+    }
+    if let Some(extra) = args.fields {
+        if !extra.is_empty() {
+            fields.push(extra);
+        }
+    }
+
+    let span = quote! {
+        tracing_etw::etw_span!(
+            target: #target,
+            name: #name,
+            #level,
+            #keyword,
+            #tag,
+            { #(#fields),* }
+        )
+    };
+
+    let body = if sig.asyncness.is_some() {
+        let output = match &sig.output {
+            ReturnType::Default => quote!(()),
+            ReturnType::Type(_, ty) => {
+                let mut ty = (**ty).clone();
+                ImplTraitEraser.visit_type_mut(&mut ty);
+                ty.into_token_stream()
+            }
+        };
+        quote_spanned! {block.span()=>
+            let __etw_span = #span;
+            tracing::Instrument::instrument(
+                async move {
+                    let __etw_result: #output = #block;
+                    __etw_result
+                },
+                __etw_span,
+            )
+            .await
+        }
+    } else {
+        quote_spanned! {block.span()=>
+            let __etw_span = #span;
+            let __etw_enter = __etw_span.enter();
+            #block
+        }
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            #body
+        }
+    })
+}
+
+// The body of an async function is given the return type of the function, so that `?` knows
+// what to convert errors into. `impl Trait` is not allowed in the type of a variable, so it is
+// replaced with `_`, as `tracing::instrument` does.
+struct ImplTraitEraser;
+
+impl VisitMut for ImplTraitEraser {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::ImplTrait(impl_trait) = ty {
+            *ty = Type::Infer(syn::TypeInfer {
+                underscore_token: syn::Token![_](impl_trait.span()),
+            });
+        } else {
+            syn::visit_mut::visit_type_mut(self, ty);
+        }
+    }
+}
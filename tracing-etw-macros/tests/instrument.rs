@@ -0,0 +1,137 @@
+use std::{future::Future, sync::Arc, task::Wake};
+
+use tracing::Level;
+use tracing_etw::{event_catalog, CatalogEntry, LayerBuilder};
+use tracing_etw_macros::etw_instrument;
+use tracing_subscriber::prelude::*;
+
+#[etw_instrument(keyword = 0x10, tag = 3, name = "Db.Query")]
+fn query(table: &str, rows: u32) -> u32 {
+    rows + table.len() as u32
+}
+
+#[etw_instrument(
+    keyword = 0x10,
+    level = "debug",
+    skip(password),
+    fields(user = "admin")
+)]
+fn connect(host: String, password: &str) -> Result<String, String> {
+    if password.is_empty() {
+        return Err("no password".to_string());
+    }
+    Ok(host)
+}
+
+#[etw_instrument(keyword = 0x20, level = Level::WARN, skip_all)]
+async fn fetch(id: u64) -> Result<u64, std::num::TryFromIntError> {
+    let id: u32 = id.try_into()?;
+    Ok(id as u64 * 2)
+}
+
+#[etw_instrument(keyword = 0x20, tag = 4)]
+async fn describe(id: u64) -> impl std::fmt::Display {
+    id
+}
+
+struct Pool;
+
+impl Pool {
+    #[etw_instrument(keyword = 0x10, target = "db::pool")]
+    fn checkout(&self, slot: usize) -> usize {
+        slot
+    }
+}
+
+#[test]
+fn instrumented_functions() {
+    let subscriber = tracing_subscriber::registry().with(
+        LayerBuilder::new("EtwInstrumentTests")
+            .__build_for_test()
+            .unwrap(),
+    );
+
+    tracing::subscriber::with_default(subscriber, || {
+        assert_eq!(query("users", 3), 8);
+        assert_eq!(
+            connect("localhost".to_string(), ""),
+            Err("no password".to_string())
+        );
+        assert_eq!(
+            connect("localhost".to_string(), "hunter2"),
+            Ok("localhost".to_string())
+        );
+        assert_eq!(Pool.checkout(4), 4);
+    });
+
+    let query = span_entry("Db.Query");
+    assert_eq!(query.keyword(), 0x10);
+    assert_eq!(query.tag(), 3);
+    assert_eq!(query.level(), Level::INFO);
+    assert_eq!(query.target(), module_path!());
+    assert_eq!(query.fields().collect::<Vec<_>>(), ["table", "rows"]);
+
+    let connect = span_entry("connect");
+    assert_eq!(connect.keyword(), 0x10);
+    assert_eq!(connect.tag(), 0);
+    assert_eq!(connect.level(), Level::DEBUG);
+    assert_eq!(connect.fields().collect::<Vec<_>>(), ["host", "user"]);
+
+    let checkout = span_entry("checkout");
+    assert_eq!(checkout.target(), "db::pool");
+    assert_eq!(checkout.fields().collect::<Vec<_>>(), ["slot"]);
+}
+
+// The catalog entry of the span of an instrumented function, which is listed once the function
+// has been called, even with the runtime_metadata feature
+fn span_entry(name: &str) -> CatalogEntry {
+    let entry = event_catalog().find(|entry| entry.name() == name).unwrap();
+    assert!(entry.is_span());
+    entry
+}
+
+#[test]
+fn instrumented_async_function() {
+    // A minimal executor is enough, since the futures never wait
+    struct NoopWaker;
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    let subscriber = tracing_subscriber::registry().with(
+        LayerBuilder::new("EtwInstrumentAsyncTests")
+            .__build_for_test()
+            .unwrap(),
+    );
+
+    tracing::subscriber::with_default(subscriber, || {
+        let waker = Arc::new(NoopWaker).into();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut fut = std::pin::pin!(fetch(21));
+        assert!(matches!(
+            fut.as_mut().poll(&mut cx),
+            std::task::Poll::Ready(Ok(42))
+        ));
+
+        let mut fut = std::pin::pin!(fetch(u64::MAX));
+        assert!(matches!(
+            fut.as_mut().poll(&mut cx),
+            std::task::Poll::Ready(Err(_))
+        ));
+
+        let mut fut = std::pin::pin!(describe(7));
+        let std::task::Poll::Ready(description) = fut.as_mut().poll(&mut cx) else {
+            panic!("describe is not ready");
+        };
+        assert_eq!(description.to_string(), "7");
+    });
+
+    let fetch = span_entry("fetch");
+    assert_eq!(fetch.keyword(), 0x20);
+    assert_eq!(fetch.level(), Level::WARN);
+    assert_eq!(fetch.fields().count(), 0);
+
+    let describe = span_entry("describe");
+    assert_eq!(describe.tag(), 4);
+    assert_eq!(describe.fields().collect::<Vec<_>>(), ["id"]);
+}